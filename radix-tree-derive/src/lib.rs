@@ -0,0 +1,298 @@
+//! `#[derive(Digital)]` for the key types of `radix-tree`.
+//!
+//! Structs are encoded field by field in declaration order, each field with its
+//! own `Digital` encoding, so that they sort like a tuple of their fields. Two
+//! attributes change how a single field is encoded:
+//!
+//! * `#[digital(descending)]` inverts the bits of the field's encoding, which
+//!   sorts the field from largest to smallest.
+//! * `#[digital(fixed_width = N)]` stores a field that derefs to `[u8]` as its raw
+//!   bytes, without the escaping and terminator variable-length fields need. The
+//!   field must always be exactly `N` bytes long, and is decoded with
+//!   `TryFrom<&[u8]>`.
+//!
+//! Fieldless enums are encoded by their discriminant, using the integer type of
+//! their `#[repr]` or `isize` if there is none.
+
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Index, LitInt, Result};
+
+#[proc_macro_derive(Digital, attributes(digital))]
+pub fn derive_digital(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let mut decodable = quote!(true);
+    let (encode, encode_with, decode) = match input.data {
+        Data::Struct(ref data) => {
+            for field in data.fields.iter() {
+                if field_options(field)?.fixed_width.is_none() {
+                    let ty = &field.ty;
+                    decodable = quote!(#decodable && <#ty as ::radix_tree::Digital>::DECODABLE);
+                }
+            }
+            let (encode, encode_with) = encode_fields(&data.fields)?;
+            (encode, encode_with, decode_fields(&data.fields)?)
+        }
+        Data::Enum(ref data) => {
+            if let Some(variant) = data.variants.iter().find(|v| !v.fields.is_empty()) {
+                return Err(Error::new(
+                    variant.span(),
+                    "Digital can only be derived for enums without fields",
+                ));
+            }
+            let repr = repr_type(input)?;
+            let variants: Vec<_> = data.variants.iter().map(|v| &v.ident).collect();
+            // Matching on the variants casts them without copying `self`, so the enum
+            // does not have to be `Copy`.
+            let discriminant = quote! {
+                let discriminant = match *self {
+                    #(#name::#variants => #name::#variants as #repr,)*
+                };
+            };
+            let encode = quote! {
+                #discriminant
+                let mut encoded = ::radix_tree::KeyBuf::new();
+                encoded.extend_from_slice(::radix_tree::Digital::encode(&discriminant).as_ref());
+                encoded
+            };
+            let encode_with = quote! {
+                #discriminant
+                ::radix_tree::Digital::encode_with(&discriminant, sink)
+            };
+            let decode = quote! {
+                let (discriminant, bytes) = <#repr as ::radix_tree::Digital>::decode(bytes)?;
+                #(
+                    if discriminant == #name::#variants as #repr {
+                        return Some((#name::#variants, bytes));
+                    }
+                )*
+                None
+            };
+            (encode, encode_with, decode)
+        }
+        Data::Union(ref data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "Digital cannot be derived for unions",
+            ))
+        }
+    };
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(syn::parse_quote!(::radix_tree::Digital));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::radix_tree::Digital for #name #ty_generics #where_clause {
+            type Encoded<'a> = ::radix_tree::KeyBuf where Self: 'a;
+            const DECODABLE: bool = #decodable;
+
+            fn encode(&self) -> Self::Encoded<'_> {
+                #encode
+            }
+
+            fn encode_with<__Sink>(&self, sink: &mut __Sink) -> bool
+            where
+                __Sink: ::std::ops::FnMut(&[u8]) -> bool,
+            {
+                #encode_with
+            }
+
+            fn decode(bytes: &[u8]) -> ::std::option::Option<(Self, &[u8])> {
+                #decode
+            }
+        }
+    })
+}
+
+/// How a field is turned into bytes.
+#[derive(Default)]
+struct FieldOptions {
+    descending: bool,
+    fixed_width: Option<usize>,
+}
+
+fn field_options(field: &syn::Field) -> Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("digital")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("descending") {
+                options.descending = true;
+                Ok(())
+            } else if meta.path.is_ident("fixed_width") {
+                let width: LitInt = meta.value()?.parse()?;
+                options.fixed_width = Some(width.base10_parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `descending` or `fixed_width = N`"))
+            }
+        })?;
+    }
+    Ok(options)
+}
+
+/// Returns the bodies of `encode` and `encode_with` for a struct.
+fn encode_fields(fields: &Fields) -> Result<(TokenStream, TokenStream)> {
+    let mut steps = Vec::new();
+    let mut feeds = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let member = match field.ident {
+            Some(ref ident) => quote!(#ident),
+            None => {
+                let index = Index::from(index);
+                quote!(#index)
+            }
+        };
+        let options = field_options(field)?;
+        let (field, feed) = match options.fixed_width {
+            Some(width) => {
+                let message = format!(
+                    "field `{}` must be exactly {} bytes long",
+                    member, width
+                );
+                let field = quote! {
+                    let field: &[u8] = ::std::convert::AsRef::<[u8]>::as_ref(&self.#member);
+                    assert_eq!(field.len(), #width, #message);
+                };
+                let feed = if options.descending {
+                    quote!(field.iter().all(|byte| sink(&[!byte])))
+                } else {
+                    quote!(sink(field))
+                };
+                let feed = quote! {{ #field #feed }};
+                (field, feed)
+            }
+            None => {
+                let field = quote! {
+                    let field = ::radix_tree::Digital::encode(&self.#member);
+                    let field: &[u8] = field.as_ref();
+                };
+                let feed = if options.descending {
+                    quote! {
+                        ::radix_tree::Digital::encode_with(&self.#member, &mut |piece: &[u8]| {
+                            piece.iter().all(|byte| sink(&[!byte]))
+                        })
+                    }
+                } else {
+                    quote!(::radix_tree::Digital::encode_with(&self.#member, &mut *sink))
+                };
+                (field, feed)
+            }
+        };
+        feeds.push(feed);
+        let append = if options.descending {
+            quote! {
+                for byte in field {
+                    encoded.push(!byte);
+                }
+            }
+        } else {
+            quote!(encoded.extend_from_slice(field);)
+        };
+        steps.push(quote! {{
+            #field
+            #append
+        }});
+    }
+    let encode = quote! {
+        let mut encoded = ::radix_tree::KeyBuf::new();
+        #(#steps)*
+        encoded
+    };
+    let encode_with = quote! {
+        #(
+            if !#feeds {
+                return false;
+            }
+        )*
+        true
+    };
+    Ok((encode, encode_with))
+}
+
+fn decode_fields(fields: &Fields) -> Result<TokenStream> {
+    let mut steps = Vec::new();
+    let mut names = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let name = format_ident!("field{}", index);
+        let ty = &field.ty;
+        let options = field_options(field)?;
+        // Descending fields are inverted back before they are decoded.
+        let source = if options.descending {
+            quote! {
+                let inverted: ::std::vec::Vec<u8> = bytes.iter().map(|byte| !byte).collect();
+                let source: &[u8] = &inverted;
+            }
+        } else {
+            quote!(let source: &[u8] = bytes;)
+        };
+        let decoded = match options.fixed_width {
+            Some(width) => quote! {
+                if source.len() < #width {
+                    return None;
+                }
+                let value =
+                    <#ty as ::std::convert::TryFrom<&[u8]>>::try_from(&source[..#width]).ok()?;
+                let used = #width;
+            },
+            None => quote! {
+                let (value, rest) = <#ty as ::radix_tree::Digital>::decode(source)?;
+                let used = source.len() - rest.len();
+            },
+        };
+        steps.push(quote! {
+            let (#name, bytes) = {
+                #source
+                #decoded
+                (value, &bytes[used..])
+            };
+        });
+        names.push(name);
+    }
+    let construct = match *fields {
+        Fields::Named(ref named) => {
+            let members = named.named.iter().map(|f| &f.ident);
+            quote!(Self { #(#members: #names),* })
+        }
+        Fields::Unnamed(_) => quote!(Self(#(#names),*)),
+        Fields::Unit => quote!(Self),
+    };
+    Ok(quote! {
+        #(#steps)*
+        Some((#construct, bytes))
+    })
+}
+
+fn repr_type(input: &DeriveInput) -> Result<syn::Ident> {
+    const INTEGERS: &[&str] = &[
+        "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+    ];
+    let mut repr = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            if let Some(ident) = meta.path.get_ident() {
+                if INTEGERS.contains(&ident.to_string().as_str()) {
+                    repr = Some(ident.clone());
+                }
+            }
+            // Skip the arguments of the other hints, like `align(8)`.
+            if meta.input.peek(syn::token::Paren) {
+                let _arguments;
+                syn::parenthesized!(_arguments in meta.input);
+            }
+            Ok(())
+        })?;
+    }
+    Ok(repr.unwrap_or_else(|| syn::Ident::new("isize", input.ident.span())))
+}
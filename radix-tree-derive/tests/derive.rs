@@ -0,0 +1,138 @@
+use radix_tree::{Digital, Radix};
+
+#[derive(Default, Clone, PartialEq, Debug, Digital)]
+struct Event {
+    tenant: u32,
+    name: String,
+    #[digital(descending)]
+    ts: i64,
+}
+
+#[derive(Default, Clone, PartialEq, Debug, Digital)]
+struct Digest(#[digital(fixed_width = 4)] Vec<u8>, u8);
+
+#[derive(Clone, Copy, PartialEq, Debug, Digital)]
+#[repr(i8)]
+enum Level {
+    Trace = -2,
+    Debug = -1,
+    Info = 0,
+    Error = 7,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Digital)]
+enum Plain {
+    A,
+    B,
+}
+
+#[derive(PartialEq, Debug, Digital)]
+#[repr(u16)]
+enum Shape {
+    Circle = 300,
+    Square,
+}
+
+fn encoded<T: Digital>(value: &T) -> Vec<u8> {
+    value.encode().as_ref().to_vec()
+}
+
+fn event(tenant: u32, name: &str, ts: i64) -> Event {
+    Event {
+        tenant,
+        name: name.to_string(),
+        ts,
+    }
+}
+
+#[test]
+fn structs_encode_their_fields_in_order() {
+    assert_eq!(
+        encoded(&event(1, "a", 0)),
+        [
+            encoded(&1u32),
+            encoded(&"a"),
+            encoded(&0i64).iter().map(|b| !b).collect(),
+        ]
+        .concat()
+    );
+    assert_eq!(encoded(&Digest(vec![1, 2, 3, 4], 5)), vec![1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn streamed_encodings_match_built_ones() {
+    fn streamed<T: Digital>(value: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        value.encode_with(&mut |piece| {
+            bytes.extend_from_slice(piece);
+            true
+        });
+        bytes
+    }
+    let event = event(1, "a\0b", -3);
+    assert_eq!(streamed(&event), encoded(&event));
+    let digest = Digest(vec![0, 1, 2, 3], 4);
+    assert_eq!(streamed(&digest), encoded(&digest));
+    assert_eq!(streamed(&Level::Trace), encoded(&Level::Trace));
+    assert_eq!(streamed(&Shape::Square), encoded(&Shape::Square));
+}
+
+#[test]
+#[should_panic(expected = "field `0` must be exactly 4 bytes long")]
+fn fixed_width_fields_are_checked() {
+    Digest(vec![1, 2, 3], 5).encode();
+}
+
+#[test]
+fn descending_fields_sort_backwards() {
+    let tree: Radix<Event, u32> = Radix::default();
+    let guard = &tree.pin();
+    let events = [
+        event(2, "x", 5),
+        event(1, "b", 1),
+        event(1, "a", 3),
+        event(1, "b", 9),
+        event(1, "b", -4),
+        event(1, "ba", 100),
+    ];
+    for (n, event) in events.iter().enumerate() {
+        tree.insert(event.clone(), n as u32, guard);
+    }
+    let order: Vec<u32> = tree.iter(guard).map(|(_, v)| *v).collect();
+    assert_eq!(order, vec![2, 3, 1, 4, 5, 0]);
+
+    let prefix = encoded(&(1u32, "b"));
+    let newest: Vec<i64> = tree
+        .scan_prefix(&prefix, guard)
+        .map(|(k, _)| k.ts)
+        .collect();
+    assert_eq!(newest, vec![9, 1, -4]);
+}
+
+#[test]
+fn enums_encode_their_discriminant() {
+    let levels = [Level::Trace, Level::Debug, Level::Info, Level::Error];
+    for pair in levels.windows(2) {
+        assert!(encoded(&pair[0]) < encoded(&pair[1]));
+    }
+    assert_eq!(encoded(&Level::Error), vec![0x87]);
+    assert_eq!(encoded(&Plain::B), encoded(&1isize));
+    assert!(encoded(&Plain::A) < encoded(&Plain::B));
+    assert_eq!(encoded(&Shape::Square), encoded(&301u16));
+}
+
+#[test]
+fn derived_keys_decode_from_their_encoding() {
+    let event = event(3, "a\0b", -9);
+    assert_eq!(Event::from_bytes(&encoded(&event)), Some(event));
+    let digest = Digest(vec![9, 0, 0, 9], 1);
+    assert_eq!(Digest::from_bytes(&encoded(&digest)), Some(digest));
+    assert_eq!(Digest::from_bytes(&[1, 2, 3]), None);
+    assert_eq!(
+        Level::from_bytes(&encoded(&Level::Trace)),
+        Some(Level::Trace)
+    );
+    assert_eq!(Level::from_bytes(&encoded(&3i8)), None);
+    assert_eq!(Plain::from_bytes(&encoded(&1isize)), Some(Plain::B));
+    assert_eq!(Shape::from_bytes(&encoded(&300u16)), Some(Shape::Circle));
+}
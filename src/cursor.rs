@@ -0,0 +1,107 @@
+use epoch::Guard;
+use internal::Digital;
+use iter::{to_bytes, RawIter};
+use node::ArtNodeRef;
+use std::ops::Bound;
+use value::ValueRef;
+
+/// A cursor over the entries of a `Radix` that moves both ways in key byte order.
+///
+/// Created by `Radix::cursor`, a cursor starts out invalid and is positioned with
+/// one of the seek methods. Like an iterator it never blocks writers: when a node
+/// on its path changes, the path is rebuilt from the current key.
+pub struct Cursor<'g, K, V>
+where
+    K: 'g + Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    // Walks on from the current entry in the direction the cursor last moved.
+    inner: RawIter<'g, K, V>,
+    forward: bool,
+    current: Option<(K, ValueRef<'g, V>)>,
+}
+
+impl<'g, K, V> Cursor<'g, K, V>
+where
+    K: 'g + Default + Clone + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    pub(crate) fn new(root: ArtNodeRef<'g, K, V>, guard: &'g Guard) -> Self {
+        Cursor {
+            inner: RawIter::new(root, guard, Bound::Unbounded, Bound::Unbounded),
+            forward: true,
+            current: None,
+        }
+    }
+
+    /// Returns `true` if the cursor is positioned on an entry.
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Returns the key of the current entry.
+    pub fn key(&self) -> Option<&K> {
+        self.current.as_ref().map(|(key, _)| key)
+    }
+
+    /// Returns the value of the current entry.
+    pub fn value(&self) -> Option<&V> {
+        self.current.as_ref().map(|(_, value)| &**value)
+    }
+
+    /// Moves to the first entry of the tree.
+    pub fn seek_to_first(&mut self) {
+        self.walk(Bound::Unbounded, Bound::Unbounded, true);
+    }
+
+    /// Moves to the last entry of the tree.
+    pub fn seek_to_last(&mut self) {
+        self.walk(Bound::Unbounded, Bound::Unbounded, false);
+    }
+
+    /// Moves to the first entry whose key is at least `key`.
+    pub fn seek(&mut self, key: &K) {
+        self.walk(to_bytes(Bound::Included(key)), Bound::Unbounded, true);
+    }
+
+    /// Moves to the last entry whose key is at most `key`.
+    pub fn seek_for_prev(&mut self, key: &K) {
+        self.walk(Bound::Unbounded, to_bytes(Bound::Included(key)), false);
+    }
+
+    /// Moves to the next entry, or becomes invalid past the last one.
+    pub fn next(&mut self) {
+        match self.current {
+            None => {}
+            Some(_) if self.forward => self.current = self.inner.next(),
+            Some((ref key, _)) => {
+                let start = to_bytes(Bound::Excluded(key));
+                self.walk(start, Bound::Unbounded, true);
+            }
+        }
+    }
+
+    /// Moves to the previous entry, or becomes invalid before the first one.
+    pub fn prev(&mut self) {
+        match self.current {
+            None => {}
+            Some(_) if !self.forward => self.current = self.inner.next_back(),
+            Some((ref key, _)) => {
+                let end = to_bytes(Bound::Excluded(key));
+                self.walk(Bound::Unbounded, end, false);
+            }
+        }
+    }
+
+    /// Positions the cursor on the first entry between `start` and `end` in the
+    /// given direction.
+    fn walk(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>, forward: bool) {
+        self.inner.reset(start, end);
+        self.forward = forward;
+        self.current = if forward {
+            self.inner.next()
+        } else {
+            self.inner.next_back()
+        };
+    }
+}
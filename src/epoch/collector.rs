@@ -1,18 +1,16 @@
+//! Epoch-based garbage collector.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! let collector = Collector::new();
+//!
+//! let handle = collector.register();
+//! drop(collector); // `handle` still works after dropping `collector`
+//!
+//! handle.pin().flush();
+//! ```
 use std::fmt;
-/// Epoch-based garbage collector.
-///
-/// # Examples
-///
-/// ```
-/// use crossbeam_epoch::Collector;
-///
-/// let collector = Collector::new();
-///
-/// let handle = collector.register();
-/// drop(collector); // `handle` still works after dropping `collector`
-///
-/// handle.pin().flush();
-/// ```
 use std::sync::Arc;
 
 use epoch::guard::Guard;
@@ -28,6 +26,8 @@ unsafe impl Sync for Collector {}
 
 impl Collector {
     /// Creates a new collector.
+    // `Global` holds raw pointers, but is shared safely, as asserted above.
+    #[allow(clippy::arc_with_non_send_sync)]
     pub fn new() -> Self {
         Collector {
             global: Arc::new(Global::new()),
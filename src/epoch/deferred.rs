@@ -11,7 +11,7 @@ use std::ptr;
 const DATA_WORDS: usize = 3;
 
 /// Some space to keep a `FnOnce()` object on the stack.
-type Data = [usize; DATA_WORDS];
+type Data = mem::MaybeUninit<[usize; DATA_WORDS]>;
 
 /// A `FnOnce()` that is stored inline if small, or otherwise boxed on the heap.
 ///
@@ -36,8 +36,8 @@ impl Deferred {
 
         unsafe {
             if size <= mem::size_of::<Data>() && align <= mem::align_of::<Data>() {
-                let mut data: Data = mem::uninitialized();
-                ptr::write(&mut data as *mut Data as *mut F, f);
+                let mut data = Data::uninit();
+                ptr::write(data.as_mut_ptr() as *mut F, f);
 
                 unsafe fn call<F: FnOnce()>(raw: *mut u8) {
                     let f: F = ptr::read(raw as *mut F);
@@ -51,8 +51,8 @@ impl Deferred {
                 }
             } else {
                 let b: Box<F> = Box::new(f);
-                let mut data: Data = mem::uninitialized();
-                ptr::write(&mut data as *mut Data as *mut Box<F>, b);
+                let mut data = Data::uninit();
+                ptr::write(data.as_mut_ptr() as *mut Box<F>, b);
 
                 unsafe fn call<F: FnOnce()>(raw: *mut u8) {
                     let b: Box<F> = ptr::read(raw as *mut Box<F>);
@@ -72,7 +72,7 @@ impl Deferred {
     #[inline]
     pub fn call(mut self) {
         let call = self.call;
-        unsafe { call(&mut self.data as *mut Data as *mut u8) };
+        unsafe { call(self.data.as_mut_ptr() as *mut u8) };
     }
 }
 
@@ -87,7 +87,7 @@ mod tests {
         let a = [0usize; 1];
 
         let d = Deferred::new(move || {
-            drop(a);
+            let _a = a;
             fired.set(true);
         });
 
@@ -102,7 +102,7 @@ mod tests {
         let a = [0usize; 10];
 
         let d = Deferred::new(move || {
-            drop(a);
+            let _a = a;
             fired.set(true);
         });
 
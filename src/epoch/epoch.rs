@@ -108,7 +108,17 @@ impl AtomicEpoch {
     /// The `Ordering` argument describes the memory ordering of this operation.
     #[inline]
     pub fn compare_and_swap(&self, current: Epoch, new: Epoch, ord: Ordering) -> Epoch {
-        let data = self.data.compare_and_swap(current.data, new.data, ord);
+        let failure = match ord {
+            Ordering::Release | Ordering::Relaxed => Ordering::Relaxed,
+            Ordering::AcqRel | Ordering::Acquire => Ordering::Acquire,
+            _ => Ordering::SeqCst,
+        };
+        let data = match self
+            .data
+            .compare_exchange(current.data, new.data, ord, failure)
+        {
+            Ok(data) | Err(data) => data,
+        };
         Epoch { data }
     }
 }
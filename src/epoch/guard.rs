@@ -11,12 +11,21 @@ pub struct Guard {
 }
 
 impl Guard {
+    /// Runs `f` once no thread pinned before this call is still pinned.
+    ///
+    /// # Safety
+    ///
+    /// `f` may run on another thread and after the guard is gone, so it must not
+    /// touch anything that could be freed or shared unsynchronized by then.
     pub unsafe fn defer<F, R>(&self, f: F)
     where
         F: FnOnce() -> R,
     {
         if let Some(local) = self.local.as_ref() {
             local.defer(Deferred::new(move || drop(f())), self);
+        } else {
+            // An unprotected guard has nobody to wait for.
+            drop(f());
         }
     }
 
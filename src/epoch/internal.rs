@@ -49,13 +49,13 @@ use epoch::collector::{Collector, Handle};
 use epoch::deferred::Deferred;
 use epoch::epoch::{AtomicEpoch, Epoch};
 use epoch::guard::{unprotected, Guard};
-use epoch::owned::Owned;
+use epoch::sync::atomic::Owned;
 use epoch::sync::list::{Entry, IsElement, IterError, List};
 use epoch::sync::queue::Queue;
 /// Maximum number of objects a bag can contain.
-#[cfg(not(feature = "sanitize"))]
+#[cfg(not(miri))]
 const MAX_OBJECTS: usize = 64;
-#[cfg(feature = "sanitize")]
+#[cfg(miri)]
 const MAX_OBJECTS: usize = 4;
 
 /// A bag of deferred functions.
@@ -93,14 +93,15 @@ impl Bag {
 
     /// Seals the bag with the given epoch.
     fn seal(self, epoch: Epoch) -> SealedBag {
-        SealedBag { epoch, bag: self }
+        SealedBag { epoch, _bag: self }
     }
 }
 
 impl Drop for Bag {
     fn drop(&mut self) {
-        // Call all deferred functions.
-        for deferred in self.deferreds.drain(..) {
+        // Call all deferred functions. They are popped one by one because
+        // `ArrayVec::drain` reads them through a borrow Miri considers invalid.
+        while let Some(deferred) = self.deferreds.pop() {
             deferred.call();
         }
     }
@@ -110,7 +111,8 @@ impl Drop for Bag {
 #[derive(Default, Debug)]
 struct SealedBag {
     epoch: Epoch,
-    bag: Bag,
+    // Dropping the bag runs its deferred functions.
+    _bag: Bag,
 }
 
 /// It is safe to share `SealedBag` because `is_expired` only inspects the epoch.
@@ -172,15 +174,15 @@ impl Global {
     pub fn collect(&self, guard: &Guard) {
         let global_epoch = self.try_advance(guard);
 
-        let steps = if cfg!(feature = "sanitize") {
-            usize::max_value()
+        let steps = if cfg!(miri) {
+            usize::MAX
         } else {
             Self::COLLECT_STEPS
         };
 
         for _ in 0..steps {
             match self.queue.try_pop_if(
-                &|sealed_bag: &SealedBag| sealed_bag.is_expired(global_epoch),
+                |sealed_bag: &SealedBag| sealed_bag.is_expired(global_epoch),
                 guard,
             ) {
                 None => break,
@@ -205,7 +207,7 @@ impl Global {
         // TODO(stjepang): `Local`s are stored in a linked list because linked lists are fairly
         // easy to implement in a lock-free manner. However, traversal can be slow due to cache
         // misses and data dependencies. We should experiment with other data structures as well.
-        for local in self.locals.iter(&guard) {
+        for local in self.locals.iter(guard) {
             match local {
                 Err(IterError::Stalled) => {
                     // A concurrent thread stalled this iteration. That thread might also try to
@@ -285,8 +287,8 @@ impl Local {
                 guard_count: Cell::new(0),
                 handle_count: Cell::new(1),
                 pin_count: Cell::new(Wrapping(0)),
-            }).into_shared(&unprotected());
-            collector.global.locals.insert(local, &unprotected());
+            }).into_shared(unprotected());
+            collector.global.locals.insert(local, unprotected());
             Handle {
                 local: local.as_raw(),
             }
@@ -302,7 +304,7 @@ impl Local {
     /// Returns a reference to the `Collector` in which this `Local` resides.
     #[inline]
     pub fn collector(&self) -> &Collector {
-        unsafe { &**self.collector.get() }
+        unsafe { &*self.collector.get() }
     }
 
     /// Returns `true` if the current participant is pinned.
@@ -376,7 +378,7 @@ impl Local {
 
             // After every `PINNINGS_BETWEEN_COLLECT` try advancing the epoch and collecting
             // some garbage.
-            if count.0 % Self::PINNINGS_BETWEEN_COLLECT == 0 {
+            if count.0.is_multiple_of(Self::PINNINGS_BETWEEN_COLLECT) {
                 self.global().collect(&guard);
             }
         }
@@ -468,7 +470,7 @@ impl Local {
             let collector: Collector = ptr::read(&*(*self.collector.get()));
 
             // Mark this node in the linked list as deleted.
-            self.entry.delete(&unprotected());
+            self.entry.delete(unprotected());
 
             // Finally, drop the reference to the global. Note that this might be the last reference
             // to the `Global`. If so, the global data will be destroyed and all deferred functions
@@ -480,14 +482,14 @@ impl Local {
 
 impl IsElement<Local> for Local {
     fn entry_of(local: &Local) -> &Entry {
-        let entry_ptr = (local as *const Local as usize + offset_of!(Local, entry)) as *const Entry;
+        let entry_ptr =
+            (local as *const Local as usize + mem::offset_of!(Local, entry)) as *const Entry;
         unsafe { &*entry_ptr }
     }
 
     unsafe fn element_of(entry: &Entry) -> &Local {
-        // offset_of! macro uses unsafe, but it's unnecessary in this context.
-        #[allow(unused_unsafe)]
-        let local_ptr = (entry as *const Entry as usize - offset_of!(Local, entry)) as *const Local;
+        let local_ptr =
+            (entry as *const Entry as usize - mem::offset_of!(Local, entry)) as *const Local;
         &*local_ptr
     }
 
@@ -1,12 +1,10 @@
-use std::mem;
-/// Returns a bitmask containing the unused least significant bits of an aligned pointer to `T`.
-#[inline]
-fn low_bits<T>() -> usize {
-    (1 << mem::align_of::<T>().trailing_zeros()) - 1
-}
+mod collector;
+mod deferred;
+#[allow(clippy::module_inception)]
+mod epoch;
+mod guard;
+mod internal;
+mod sync;
 
-/// Panics if the pointer is not properly unaligned.
-#[inline]
-fn ensure_aligned<T>(raw: *const T) {
-    assert_eq!(raw as usize & low_bits::<T>(), 0, "unaligned pointer");
-}
+pub use self::collector::{Collector, Handle};
+pub use self::guard::Guard;
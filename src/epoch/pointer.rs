@@ -1,8 +0,0 @@
-/// A trait for either `Owned` or `Shared` pointers.
-pub trait Pointer<T> {
-    /// Returns the machine representation of the pointer.
-    fn into_usize(self) -> usize;
-
-    /// Returns a new pointer pointing to the tagged pointer `data`.
-    unsafe fn from_usize(data: usize) -> Self;
-}
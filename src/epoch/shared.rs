@@ -1,246 +0,0 @@
-use epoch::ensure_aligned;
-use epoch::owned::Owned;
-use epoch::pointer::Pointer;
-use std::cmp;
-use std::fmt;
-use std::marker::PhantomData;
-use std::ptr;
-/// A pointer to an object protected by the epoch GC.
-///
-/// The pointer is valid for use only during the lifetime `'g`.
-///
-/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
-/// least significant bits of the address.
-pub struct Shared<'g, T: 'g> {
-    pub data: usize,
-    _marker: PhantomData<(&'g (), *const T)>,
-}
-
-impl<'g, T> Clone for Shared<'g, T> {
-    fn clone(&self) -> Self {
-        Shared {
-            data: self.data,
-            _marker: PhantomData,
-        }
-    }
-}
-
-impl<'g, T> Copy for Shared<'g, T> {}
-
-impl<'g, T> Pointer<T> for Shared<'g, T> {
-    #[inline]
-    fn into_usize(self) -> usize {
-        self.data
-    }
-
-    #[inline]
-    unsafe fn from_usize(data: usize) -> Self {
-        Shared {
-            data: data,
-            _marker: PhantomData,
-        }
-    }
-}
-
-impl<'g, T> Shared<'g, T> {
-    /// Returns a new null pointer.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use epoch::Shared;
-    ///
-    /// let p = Shared::<i32>::null();
-    /// assert!(p.is_null());
-    /// ```
-    pub fn null() -> Shared<'g, T> {
-        Shared {
-            data: 0,
-            _marker: PhantomData,
-        }
-    }
-
-    /// Converts the pointer to a raw pointer (without the tag).
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use epoch::{self as epoch, Atomic, Owned};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let o = Owned::new(1234);
-    /// let raw = &*o as *const _;
-    /// let a = Atomic::from(o);
-    ///
-    /// let guard = &epoch::pin();
-    /// let p = a.load(SeqCst, guard);
-    /// assert_eq!(p.as_raw(), raw);
-    /// ```
-    pub fn as_raw(&self) -> *const T {
-        let raw = self.data as *mut T;
-        raw
-    }
-
-    /// Dereferences the pointer.
-    ///
-    /// Returns a reference to the pointee that is valid during the lifetime `'g`.
-    ///
-    /// # Safety
-    ///
-    /// Dereferencing a pointer is unsafe because it could be pointing to invalid memory.
-    ///
-    /// Another concern is the possiblity of data races due to lack of proper synchronization.
-    /// For example, consider the following scenario:
-    ///
-    /// 1. A thread creates a new object: `a.store(Owned::new(10), Relaxed)`
-    /// 2. Another thread reads it: `*a.load(Relaxed, guard).as_ref().unwrap()`
-    ///
-    /// The problem is that relaxed orderings don't synchronize initialization of the object with
-    /// the read from the second thread. This is a data race. A possible solution would be to use
-    /// `Release` and `Acquire` orderings.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use epoch::{self as epoch, Atomic};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::new(1234);
-    /// let guard = &epoch::pin();
-    /// let p = a.load(SeqCst, guard);
-    /// unsafe {
-    ///     assert_eq!(p.deref(), &1234);
-    /// }
-    /// ```
-    pub unsafe fn deref(&self) -> &'g T {
-        &*self.as_raw()
-    }
-
-    /// Converts the pointer to a reference.
-    ///
-    /// Returns `None` if the pointer is null, or else a reference to the object wrapped in `Some`.
-    ///
-    /// # Safety
-    ///
-    /// Dereferencing a pointer is unsafe because it could be pointing to invalid memory.
-    ///
-    /// Another concern is the possiblity of data races due to lack of proper synchronization.
-    /// For example, consider the following scenario:
-    ///
-    /// 1. A thread creates a new object: `a.store(Owned::new(10), Relaxed)`
-    /// 2. Another thread reads it: `*a.load(Relaxed, guard).as_ref().unwrap()`
-    ///
-    /// The problem is that relaxed orderings don't synchronize initialization of the object with
-    /// the read from the second thread. This is a data race. A possible solution would be to use
-    /// `Release` and `Acquire` orderings.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use epoch::{self as epoch, Atomic};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::new(1234);
-    /// let guard = &epoch::pin();
-    /// let p = a.load(SeqCst, guard);
-    /// unsafe {
-    ///     assert_eq!(p.as_ref(), Some(&1234));
-    /// }
-    /// ```
-    pub unsafe fn as_ref(&self) -> Option<&'g T> {
-        self.as_raw().as_ref()
-    }
-
-    /// Takes ownership of the pointee.
-    ///
-    /// # Panics
-    ///
-    /// Panics if this pointer is null, but only in debug mode.
-    ///
-    /// # Safety
-    ///
-    /// This method may be called only if the pointer is valid and nobody else is holding a
-    /// reference to the same object.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use epoch::{self as epoch, Atomic};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::new(1234);
-    /// unsafe {
-    ///     let guard = &epoch::unprotected();
-    ///     let p = a.load(SeqCst, guard);
-    ///     drop(p.into_owned());
-    /// }
-    /// ```
-    pub unsafe fn into_owned(self) -> Owned<T> {
-        debug_assert!(
-            self.as_raw() != ptr::null(),
-            "converting a null `Shared` into `Owned`"
-        );
-        Owned::from_usize(self.data)
-    }
-}
-
-impl<'g, T> PartialEq<Shared<'g, T>> for Shared<'g, T> {
-    fn eq(&self, other: &Self) -> bool {
-        self.data == other.data
-    }
-}
-
-impl<'g, T> Eq for Shared<'g, T> {}
-
-impl<'g, T> PartialOrd<Shared<'g, T>> for Shared<'g, T> {
-    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        self.data.partial_cmp(&other.data)
-    }
-}
-
-impl<'g, T> Ord for Shared<'g, T> {
-    fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.data.cmp(&other.data)
-    }
-}
-
-impl<'g, T> From<*const T> for Shared<'g, T> {
-    /// Returns a new pointer pointing to `raw`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if `raw` is not properly aligned.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use epoch::Shared;
-    ///
-    /// let p = unsafe { Shared::from(Box::into_raw(Box::new(1234)) as *const _) };
-    /// assert!(!p.is_null());
-    /// ```
-    fn from(raw: *const T) -> Self {
-        ensure_aligned(raw);
-        unsafe { Self::from_usize(raw as usize) }
-    }
-}
-
-impl<'g, T> fmt::Debug for Shared<'g, T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let raw = self.data as *mut T;
-
-        f.debug_struct("Shared").field("raw", &raw).finish()
-    }
-}
-
-impl<'g, T> fmt::Pointer for Shared<'g, T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Pointer::fmt(&self.as_raw(), f)
-    }
-}
-
-impl<'g, T> Default for Shared<'g, T> {
-    fn default() -> Self {
-        Shared::null()
-    }
-}
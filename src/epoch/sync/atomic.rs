@@ -1,3 +1,7 @@
+// Kept close to crossbeam-epoch's pointer types, including the methods the tree
+// does not call.
+#![allow(dead_code)]
+
 use std::borrow::{Borrow, BorrowMut};
 use std::boxed::Box;
 use std::cmp;
@@ -5,9 +9,8 @@ use std::fmt;
 use std::marker::PhantomData;
 use std::mem;
 use std::ops::{Deref, DerefMut};
-use std::ptr;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
-use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
 
 use crossbeam_utils::consume::AtomicConsume;
 use epoch::guard::Guard;
@@ -146,32 +149,14 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Atomic;
     ///
     /// let a = Atomic::<i32>::null();
     /// ```
-    #[cfg(not(feature = "nightly"))]
-    pub fn null() -> Atomic<T> {
-        Self {
-            data: ATOMIC_USIZE_INIT,
-            _marker: PhantomData,
-        }
-    }
-
-    /// Returns a new null atomic pointer.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use crossbeam_epoch::Atomic;
-    ///
-    /// let a = Atomic::<i32>::null();
-    /// ```
-    #[cfg(feature = "nightly")]
     pub const fn null() -> Atomic<T> {
         Self {
-            data: ATOMIC_USIZE_INIT,
+            data: AtomicUsize::new(0),
             _marker: PhantomData,
         }
     }
@@ -180,7 +165,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Atomic;
     ///
     /// let a = Atomic::new(1234);
@@ -198,7 +183,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -224,7 +209,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic};
     ///
     /// let a = Atomic::new(1234);
@@ -244,7 +229,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Shared};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -252,7 +237,7 @@ impl<T> Atomic<T> {
     /// a.store(Shared::null(), SeqCst);
     /// a.store(Owned::new(1234), SeqCst);
     /// ```
-    pub fn store<'g, P: Pointer<T>>(&self, new: P, ord: Ordering) {
+    pub fn store<P: Pointer<T>>(&self, new: P, ord: Ordering) {
         self.data.store(new.into_usize(), ord);
     }
 
@@ -266,7 +251,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Shared};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -293,7 +278,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Shared};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -344,7 +329,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Shared};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -409,7 +394,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Shared};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -434,7 +419,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Shared};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -459,7 +444,7 @@ impl<T> Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Shared};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -515,7 +500,7 @@ impl<T> From<Owned<T>> for Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{Atomic, Owned};
     ///
     /// let a = Atomic::<i32>::from(Owned::new(1234));
@@ -544,7 +529,7 @@ impl<'g, T> From<Shared<'g, T>> for Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{Atomic, Shared};
     ///
     /// let a = Atomic::<i32>::from(Shared::<i32>::null());
@@ -559,7 +544,7 @@ impl<T> From<*const T> for Atomic<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use std::ptr;
     /// use crossbeam_epoch::Atomic;
     ///
@@ -607,7 +592,7 @@ impl<T> Pointer<T> for Owned<T> {
     unsafe fn from_usize(data: usize) -> Self {
         debug_assert!(data != 0, "converting zero into `Owned`");
         Owned {
-            data: data,
+            data,
             _marker: PhantomData,
         }
     }
@@ -618,7 +603,7 @@ impl<T> Owned<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Owned;
     ///
     /// let o = Owned::new(1234);
@@ -639,7 +624,7 @@ impl<T> Owned<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Owned;
     ///
     /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
@@ -653,7 +638,7 @@ impl<T> Owned<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Owned};
     ///
     /// let o = Owned::new(1234);
@@ -670,7 +655,7 @@ impl<T> Owned<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Owned};
     ///
     /// let o = Owned::new(1234);
@@ -687,7 +672,7 @@ impl<T> Owned<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Owned;
     ///
     /// assert_eq!(Owned::new(1234).tag(), 0);
@@ -702,7 +687,7 @@ impl<T> Owned<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Owned;
     ///
     /// let o = Owned::new(0u64);
@@ -773,7 +758,7 @@ impl<T> From<Box<T>> for Owned<T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Owned;
     ///
     /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
@@ -785,25 +770,25 @@ impl<T> From<Box<T>> for Owned<T> {
 
 impl<T> Borrow<T> for Owned<T> {
     fn borrow(&self) -> &T {
-        &**self
+        self
     }
 }
 
 impl<T> BorrowMut<T> for Owned<T> {
     fn borrow_mut(&mut self) -> &mut T {
-        &mut **self
+        self
     }
 }
 
 impl<T> AsRef<T> for Owned<T> {
     fn as_ref(&self) -> &T {
-        &**self
+        self
     }
 }
 
 impl<T> AsMut<T> for Owned<T> {
     fn as_mut(&mut self) -> &mut T {
-        &mut **self
+        self
     }
 }
 
@@ -820,10 +805,7 @@ pub struct Shared<'g, T: 'g> {
 
 impl<'g, T> Clone for Shared<'g, T> {
     fn clone(&self) -> Self {
-        Shared {
-            data: self.data,
-            _marker: PhantomData,
-        }
+        *self
     }
 }
 
@@ -838,7 +820,7 @@ impl<'g, T> Pointer<T> for Shared<'g, T> {
     #[inline]
     unsafe fn from_usize(data: usize) -> Self {
         Shared {
-            data: data,
+            data,
             _marker: PhantomData,
         }
     }
@@ -849,7 +831,7 @@ impl<'g, T> Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Shared;
     ///
     /// let p = Shared::<i32>::null();
@@ -866,7 +848,7 @@ impl<'g, T> Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Owned};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -884,7 +866,7 @@ impl<'g, T> Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Owned};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -921,7 +903,7 @@ impl<'g, T> Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -956,7 +938,7 @@ impl<'g, T> Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -984,7 +966,7 @@ impl<'g, T> Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -997,7 +979,7 @@ impl<'g, T> Shared<'g, T> {
     /// ```
     pub unsafe fn into_owned(self) -> Owned<T> {
         debug_assert!(
-            self.as_raw() != ptr::null(),
+            !self.as_raw().is_null(),
             "converting a null `Shared` into `Owned`"
         );
         Owned::from_usize(self.data)
@@ -1007,7 +989,7 @@ impl<'g, T> Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic, Owned};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -1026,7 +1008,7 @@ impl<'g, T> Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::{self as epoch, Atomic};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
@@ -1053,7 +1035,7 @@ impl<'g, T> From<*const T> for Shared<'g, T> {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use crossbeam_epoch::Shared;
     ///
     /// let p = unsafe { Shared::from(Box::into_raw(Box::new(1234)) as *const _) };
@@ -1075,7 +1057,7 @@ impl<'g, T> Eq for Shared<'g, T> {}
 
 impl<'g, T> PartialOrd<Shared<'g, T>> for Shared<'g, T> {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        self.data.partial_cmp(&other.data)
+        Some(self.cmp(other))
     }
 }
 
@@ -68,7 +68,7 @@ pub struct Entry {
 ///
 pub trait IsElement<T> {
     /// Returns a reference to this element's `Entry`.
-    fn entry_of(&T) -> &Entry;
+    fn entry_of(_: &T) -> &Entry;
 
     /// Given a reference to an element's entry, returns that element.
     ///
@@ -81,7 +81,7 @@ pub trait IsElement<T> {
     /// # Safety
     /// The caller has to guarantee that the `Entry` it
     /// is called with was retrieved from an instance of the element type (`T`).
-    unsafe fn element_of(&Entry) -> &T;
+    unsafe fn element_of(_: &Entry) -> &T;
 
     /// Deallocates the whole element given its `Entry`. This is called when the list
     /// is ready to actually free the element.
@@ -89,7 +89,7 @@ pub trait IsElement<T> {
     /// # Safety
     /// The caller has to guarantee that the `Entry` it
     /// is called with was retrieved from an instance of the element type (`T`).
-    unsafe fn finalize(&Entry);
+    unsafe fn finalize(_: &Entry);
 }
 
 /// A lock-free, intrusive linked list of type `T`.
@@ -290,7 +290,8 @@ impl<'g, T: 'g, C: IsElement<T>> Iterator for Iter<'g, T, C> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crossbeam_epoch::{Collector, Owned};
+    use epoch::collector::Collector;
+    use epoch::sync::atomic::Owned;
     use crossbeam_utils::scoped;
     use std::sync::Barrier;
 
@@ -331,13 +332,13 @@ mod tests {
         let mut iter = l.iter(&guard);
         let maybe_e3 = iter.next();
         assert!(maybe_e3.is_some());
-        assert!(maybe_e3.unwrap().unwrap() as *const Entry == e3.as_raw());
+        assert!(std::ptr::eq(maybe_e3.unwrap().unwrap(), e3.as_raw()));
         let maybe_e2 = iter.next();
         assert!(maybe_e2.is_some());
-        assert!(maybe_e2.unwrap().unwrap() as *const Entry == e2.as_raw());
+        assert!(std::ptr::eq(maybe_e2.unwrap().unwrap(), e2.as_raw()));
         let maybe_e1 = iter.next();
         assert!(maybe_e1.is_some());
-        assert!(maybe_e1.unwrap().unwrap() as *const Entry == e1.as_raw());
+        assert!(std::ptr::eq(maybe_e1.unwrap().unwrap(), e1.as_raw()));
         assert!(iter.next().is_none());
 
         unsafe {
@@ -370,10 +371,10 @@ mod tests {
         let mut iter = l.iter(&guard);
         let maybe_e3 = iter.next();
         assert!(maybe_e3.is_some());
-        assert!(maybe_e3.unwrap().unwrap() as *const Entry == e3.as_raw());
+        assert!(std::ptr::eq(maybe_e3.unwrap().unwrap(), e3.as_raw()));
         let maybe_e1 = iter.next();
         assert!(maybe_e1.is_some());
-        assert!(maybe_e1.unwrap().unwrap() as *const Entry == e1.as_raw());
+        assert!(std::ptr::eq(maybe_e1.unwrap().unwrap(), e1.as_raw()));
         assert!(iter.next().is_none());
 
         unsafe {
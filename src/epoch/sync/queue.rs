@@ -5,17 +5,13 @@
 //! Michael and Scott.  Simple, Fast, and Practical Non-Blocking and Blocking Concurrent Queue
 //! Algorithms.  PODC 1996.  http://dl.acm.org/citation.cfm?id=248106
 
-use std::mem::{self, ManuallyDrop};
+use std::mem::MaybeUninit;
 use std::ptr;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 
 use crossbeam_utils::cache_padded::CachePadded;
-use epoch::collector::{Collector, Handle};
-use epoch::deferred::Deferred;
-use epoch::epoch::{AtomicEpoch, Epoch};
 use epoch::guard::{unprotected, Guard};
 use epoch::sync::atomic::{Atomic, Owned, Shared};
-use epoch::sync::list::{Entry, IsElement, IterError, List};
 // The representation here is a singly-linked list, with a sentinel node at the front. In general
 // the `tail` pointer may lag behind the actual tail. Non-sentinel nodes are either all `Data` or
 // all `Blocked` (requests for data from blocked threads).
@@ -29,11 +25,11 @@ pub struct Queue<T> {
 struct Node<T> {
     /// The slot in which a value of type `T` can be stored.
     ///
-    /// The type of `data` is `ManuallyDrop<T>` because a `Node<T>` doesn't always contain a `T`.
+    /// The type of `data` is `MaybeUninit<T>` because a `Node<T>` doesn't always contain a `T`.
     /// For example, the sentinel node in a queue never contains a value: its slot is always empty.
     /// Other nodes start their life with a push operation and contain a value until it gets popped
     /// out. After that such empty nodes get added to the collector for destruction.
-    data: ManuallyDrop<T>,
+    data: MaybeUninit<T>,
 
     next: Atomic<Node<T>>,
 }
@@ -50,7 +46,7 @@ impl<T> Queue<T> {
             tail: CachePadded::new(Atomic::null()),
         };
         let sentinel = Owned::new(Node {
-            data: unsafe { mem::uninitialized() },
+            data: MaybeUninit::uninit(),
             next: Atomic::null(),
         });
         unsafe {
@@ -90,7 +86,7 @@ impl<T> Queue<T> {
     /// Adds `t` to the back of the queue, possibly waking up threads blocked on `pop`.
     pub fn push(&self, t: T, guard: &Guard) {
         let new = Owned::new(Node {
-            data: ManuallyDrop::new(t),
+            data: MaybeUninit::new(t),
             next: Atomic::null(),
         });
         let new = Owned::into_shared(new, guard);
@@ -118,7 +114,7 @@ impl<T> Queue<T> {
                     .compare_and_set(head, next, Release, guard)
                     .map(|_| {
                         guard.defer(move || drop(head.into_owned()));
-                        Some(ManuallyDrop::into_inner(ptr::read(&n.data)))
+                        Some(ptr::read(n.data.as_ptr()))
                     })
                     .map_err(|_| ())
             },
@@ -138,12 +134,12 @@ impl<T> Queue<T> {
         let h = unsafe { head.deref() };
         let next = h.next.load(Acquire, guard);
         match unsafe { next.as_ref() } {
-            Some(n) if condition(&n.data) => unsafe {
+            Some(n) if condition(unsafe { &*n.data.as_ptr() }) => unsafe {
                 self.head
                     .compare_and_set(head, next, Release, guard)
                     .map(|_| {
                         guard.defer(move || drop(head.into_owned()));
-                        Some(ManuallyDrop::into_inner(ptr::read(&n.data)))
+                        Some(ptr::read(n.data.as_ptr()))
                     })
                     .map_err(|_| ())
             },
@@ -184,7 +180,7 @@ impl<T> Drop for Queue<T> {
         unsafe {
             let guard = &unprotected();
 
-            while let Some(_) = self.try_pop(guard) {}
+            while self.try_pop(guard).is_some() {}
 
             // Destroy the remaining sentinel node.
             let sentinel = self.head.load(Relaxed, guard);
@@ -197,33 +193,39 @@ impl<T> Drop for Queue<T> {
 mod test {
     use super::*;
     use crossbeam_utils::scoped;
-    use pin;
+    use epoch::Collector;
 
     struct Queue<T> {
         queue: super::Queue<T>,
+        collector: Collector,
     }
 
     impl<T> Queue<T> {
         pub fn new() -> Queue<T> {
             Queue {
                 queue: super::Queue::new(),
+                collector: Collector::new(),
             }
         }
 
+        fn pin(&self) -> Guard {
+            self.collector.register().pin()
+        }
+
         pub fn push(&self, t: T) {
-            let guard = &pin();
+            let guard = &self.pin();
             self.queue.push(t, guard);
         }
 
         pub fn is_empty(&self) -> bool {
-            let guard = &pin();
+            let guard = &self.pin();
             let head = self.queue.head.load(Acquire, guard);
             let h = unsafe { head.deref() };
             h.next.load(Acquire, guard).is_null()
         }
 
         pub fn try_pop(&self) -> Option<T> {
-            let guard = &pin();
+            let guard = &self.pin();
             self.queue.try_pop(guard)
         }
 
@@ -237,6 +239,9 @@ mod test {
         }
     }
 
+    #[cfg(miri)]
+    const CONC_COUNT: i64 = 1000;
+    #[cfg(not(miri))]
     const CONC_COUNT: i64 = 1000000;
 
     #[test]
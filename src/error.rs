@@ -0,0 +1,67 @@
+use std::error::Error;
+use std::fmt;
+use std::hint;
+use std::thread;
+
+/// The error returned by the operations of a `Radix` that can fail.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RadixError<V> {
+    /// The key is already present. Holds the value that was not inserted.
+    KeyExists(V),
+}
+
+impl<V> fmt::Display for RadixError<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RadixError::KeyExists(_) => f.write_str("key already exists"),
+        }
+    }
+}
+
+impl<V: fmt::Debug> Error for RadixError<V> {}
+
+/// An optimistic attempt to read a node that changed or became obsolete under it.
+///
+/// Nothing was modified, the operation starts over from the root. Restarts never
+/// leave the crate: public operations retry until an attempt gets through.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Restart;
+
+/// Runs `attempt` until it does not restart, backing off between attempts.
+pub fn retry<R, F>(mut attempt: F) -> R
+where
+    F: FnMut() -> Result<R, Restart>,
+{
+    let mut backoff = Backoff::new();
+    loop {
+        match attempt() {
+            Ok(result) => return result,
+            Err(Restart) => backoff.snooze(),
+        }
+    }
+}
+
+// The longest spin takes `1 << SPIN_LIMIT` iterations, past it the thread yields.
+const SPIN_LIMIT: u32 = 6;
+
+/// Waits a little longer every time a writer got in the way again, up to a yield.
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Backoff { step: 0 }
+    }
+
+    fn snooze(&mut self) {
+        if self.step <= SPIN_LIMIT {
+            for _ in 0..1 << self.step {
+                hint::spin_loop();
+            }
+            self.step += 1;
+        } else {
+            thread::yield_now();
+        }
+    }
+}
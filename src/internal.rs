@@ -1,56 +1,652 @@
+use std::cmp::Ordering;
+use std::convert::TryInto;
 use std::mem;
 
-#[macro_export]
-macro_rules! rep_no_copy {
-    ($t:ty; $e:expr; $n:expr) => {{
-        let mut v: Vec<$t> = Vec::with_capacity($n);
-        for _ in 0..$n {
-            v.push($e);
+/// A key type whose byte encoding sorts like the keys themselves.
+///
+/// The tree only ever looks at encoded keys: it descends by their bytes and tells
+/// keys apart by comparing encodings. No encoding may be a prefix of another one.
+///
+/// With the `derive` feature, `#[derive(Digital)]` implements it for structs and
+/// fieldless enums.
+///
+/// Encoding a number never allocates. Strings, byte vectors and composite keys
+/// are encoded into a `KeyBuf`, which moves to the heap once the encoding
+/// outgrows 32 bytes. The tree encodes the key it is handed once, and compares
+/// the keys of its leaves with it through `encode_with`, which never builds their
+/// encoding. So a lookup allocates only for a key that encodes to more than 32
+/// bytes, and then once.
+pub trait Digital {
+    /// The encoded key: an array for numbers, a `KeyBuf` for everything else.
+    type Encoded<'a>: AsRef<[u8]>
+    where
+        Self: 'a;
+
+    fn encode(&self) -> Self::Encoded<'_>;
+
+    /// Feeds the encoding to `sink` piece by piece, until `sink` returns `false`.
+    /// Returns `false` if the encoding was cut short that way.
+    ///
+    /// Keys encoded into a `KeyBuf` stream their encoding instead of building it,
+    /// so that comparing keys never allocates.
+    fn encode_with<F: FnMut(&[u8]) -> bool>(&self, sink: &mut F) -> bool {
+        sink(self.encode().as_ref())
+    }
+
+    /// Whether `decode` recovers every key. Trees only leave the key out of their
+    /// leaves when it can be recovered from the path to the leaf.
+    const DECODABLE: bool = true;
+
+    /// Decodes the key at the front of `bytes` and returns it with the bytes that
+    /// follow it, or `None` if `bytes` does not start with an encoded key.
+    ///
+    /// Borrowed keys like `&str` have nothing to borrow from and never decode.
+    fn decode(bytes: &[u8]) -> Option<(Self, &[u8])>
+    where
+        Self: Sized;
+
+    /// Decodes a key from exactly its encoding.
+    fn from_bytes(bytes: &[u8]) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        match Self::decode(bytes) {
+            Some((key, [])) => Some(key),
+            _ => None,
         }
-        v
-    }};
+    }
 }
 
-#[macro_export]
-macro_rules! matches {
-    ($e:expr, $p:pat) => {
-        match $e {
-            $p => true,
-            _ => false,
+/// Splits the first `N` bytes off `bytes`.
+fn split_array<const N: usize>(bytes: &[u8]) -> Option<([u8; N], &[u8])> {
+    if bytes.len() < N {
+        return None;
+    }
+    let mut head = [0; N];
+    head.copy_from_slice(&bytes[..N]);
+    Some((head, &bytes[N..]))
+}
+
+/// Number of bytes a `KeyBuf` holds before it moves to the heap.
+const INLINE_KEY_LEN: usize = 32;
+
+/// A growable encoded key that stays on the stack while it is short.
+///
+/// Encodings of strings and composite keys are built in one, so that encoding a
+/// key of up to 32 bytes does not allocate. Longer encodings are copied into a
+/// `Vec` as they pass the limit.
+pub struct KeyBuf {
+    len: usize,
+    inline: [u8; INLINE_KEY_LEN],
+    spilled: Vec<u8>,
+}
+
+impl KeyBuf {
+    pub fn new() -> Self {
+        KeyBuf {
+            len: 0,
+            inline: [0; INLINE_KEY_LEN],
+            spilled: Vec::new(),
         }
-    };
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        if self.len < INLINE_KEY_LEN {
+            self.inline[self.len] = byte;
+        } else {
+            if self.spilled.is_empty() {
+                self.spilled.extend_from_slice(&self.inline);
+            }
+            self.spilled.push(byte);
+        }
+        self.len += 1;
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.push(*byte);
+        }
+    }
+}
+
+impl Default for KeyBuf {
+    fn default() -> Self {
+        KeyBuf::new()
+    }
+}
+
+impl AsRef<[u8]> for KeyBuf {
+    fn as_ref(&self) -> &[u8] {
+        if self.len <= INLINE_KEY_LEN {
+            &self.inline[..self.len]
+        } else {
+            &self.spilled
+        }
+    }
+}
+
+// Integers are stored big-endian so that byte order is numeric order. Signed ones
+// get their sign bit flipped on top, which moves the negative numbers below the
+// positive ones.
+macro_rules! impl_digital_unsigned {
+    ($($t:ty),*) => {$(
+        impl Digital for $t {
+            type Encoded<'a> = [u8; mem::size_of::<$t>()];
+
+            fn encode(&self) -> Self::Encoded<'_> {
+                self.to_be_bytes()
+            }
+
+            fn decode(bytes: &[u8]) -> Option<(Self, &[u8])> {
+                let (head, rest) = split_array(bytes)?;
+                Some((<$t>::from_be_bytes(head), rest))
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_digital_signed {
+    ($($t:ty => $u:ty),*) => {$(
+        impl Digital for $t {
+            type Encoded<'a> = [u8; mem::size_of::<$t>()];
+
+            fn encode(&self) -> Self::Encoded<'_> {
+                let flipped = (*self as $u) ^ (1 << (mem::size_of::<$u>() * 8 - 1));
+                flipped.to_be_bytes()
+            }
+
+            fn decode(bytes: &[u8]) -> Option<(Self, &[u8])> {
+                let (head, rest) = split_array(bytes)?;
+                let flipped = <$u>::from_be_bytes(head);
+                Some(((flipped ^ (1 << (mem::size_of::<$u>() * 8 - 1))) as $t, rest))
+            }
+        }
+    )*};
+}
+
+impl_digital_unsigned!(u8, u16, u32, u64, u128, usize);
+impl_digital_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize);
+
+macro_rules! impl_digital_float {
+    ($($t:ty => $u:ty),*) => {$(
+        /// Floats are stored by their bits, big-endian. Positive numbers get the
+        /// sign bit set, negative ones get all bits flipped so that a larger
+        /// magnitude sorts lower. Keys then sort in the IEEE 754 total order:
+        ///
+        /// ```text
+        /// -NaN < -inf < ... < -0.0 < +0.0 < ... < +inf < +NaN
+        /// ```
+        ///
+        /// -0.0 and +0.0 are distinct keys, and so is every NaN payload. A NaN key
+        /// can be looked up like any other key, as lookups compare encodings and
+        /// not values.
+        impl Digital for $t {
+            type Encoded<'a> = [u8; mem::size_of::<$t>()];
+
+            fn encode(&self) -> Self::Encoded<'_> {
+                let bits = self.to_bits();
+                let sign: $u = 1 << (mem::size_of::<$u>() * 8 - 1);
+                let flipped = if bits & sign == 0 { bits | sign } else { !bits };
+                flipped.to_be_bytes()
+            }
+
+            fn decode(bytes: &[u8]) -> Option<(Self, &[u8])> {
+                let (head, rest) = split_array(bytes)?;
+                let flipped = <$u>::from_be_bytes(head);
+                let sign: $u = 1 << (mem::size_of::<$u>() * 8 - 1);
+                let bits = if flipped & sign != 0 { flipped ^ sign } else { !flipped };
+                Some((<$t>::from_bits(bits), rest))
+            }
+        }
+    )*};
+}
+
+impl_digital_float!(f32 => u32, f64 => u64);
+
+/// Appends `bytes` to `out` with every zero byte escaped as `[0, 0xff]`, and
+/// terminates them with `[0, 0]`.
+///
+/// No escaped string is a prefix of another one, and comparing them byte by byte
+/// orders them like the strings themselves, shorter before longer.
+pub fn escape_into(bytes: &[u8], out: &mut KeyBuf) {
+    escape_with(bytes, &mut |piece| {
+        out.extend_from_slice(piece);
+        true
+    });
+}
+
+/// Feeds `bytes` escaped like `escape_into` does to `sink`, see
+/// `Digital::encode_with`.
+pub fn escape_with<F: FnMut(&[u8]) -> bool>(bytes: &[u8], sink: &mut F) -> bool {
+    for piece in bytes.split_inclusive(|byte| *byte == 0) {
+        if !sink(piece) {
+            return false;
+        }
+        if piece.ends_with(&[0]) && !sink(&[0xff]) {
+            return false;
+        }
+    }
+    sink(&[0, 0])
+}
+
+/// Reverses `escape_into` on the front of `bytes`, returning the string and the
+/// bytes after its terminator.
+pub fn unescape(bytes: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let mut unescaped = Vec::new();
+    let mut pos = 0;
+    loop {
+        match *bytes.get(pos)? {
+            0 => match *bytes.get(pos + 1)? {
+                0 => return Some((unescaped, &bytes[pos + 2..])),
+                0xff => unescaped.push(0),
+                _ => return None,
+            },
+            byte => {
+                unescaped.push(byte);
+                pos += 1;
+                continue;
+            }
+        }
+        pos += 2;
+    }
 }
 
-#[macro_export]
-macro_rules! make_array {
-    ($n:expr, $constructor:expr) => {{
-        let mut items: [_; $n] = std::mem::uninitialized();
-        for place in items.iter_mut() {
-            std::ptr::write(place, $constructor);
+macro_rules! impl_digital_bytes {
+    ($($t:ty => $decode:expr, $decodable:expr);*) => {$(
+        impl<'b> Digital for $t {
+            type Encoded<'a> = KeyBuf where Self: 'a;
+            const DECODABLE: bool = $decodable;
+
+            fn encode(&self) -> Self::Encoded<'_> {
+                let mut encoded = KeyBuf::new();
+                escape_into(self.as_ref(), &mut encoded);
+                encoded
+            }
+
+            fn encode_with<F: FnMut(&[u8]) -> bool>(&self, sink: &mut F) -> bool {
+                escape_with(self.as_ref(), sink)
+            }
+
+            fn decode(bytes: &[u8]) -> Option<(Self, &[u8])> {
+                $decode(bytes)
+            }
         }
-        items
-    }};
+    )*};
 }
 
-#[macro_export]
-macro_rules! read_unlock_or_restart {
-    ($n:ident,$m:ident) => {
-        match $n {
-            ArtNode::Empty => true,
-            ArtNode::Inner4(ptr) => NodeHeader::read_unlock_or_restart(ptr.get_version(), $m),
-            ArtNode::Inner16(ptr) => NodeHeader::read_unlock_or_restart(ptr.get_version(), $m),
-            ArtNode::Inner48(ptr) => NodeHeader::read_unlock_or_restart(ptr.get_version(), $m),
-            ArtNode::Inner256(ptr) => NodeHeader::read_unlock_or_restart(ptr.get_version(), $m),
-            ArtNode::Value(_) => true,
+impl_digital_bytes!(
+    String => |bytes| {
+        let (string, rest) = unescape(bytes)?;
+        Some((String::from_utf8(string).ok()?, rest))
+    }, true;
+    Vec<u8> => unescape, true;
+    &'b str => |_| None, false;
+    &'b [u8] => |_| None, false
+);
+
+// Composite keys are the concatenation of their components. Every encoding above
+// is either of fixed length or escaped and terminated, so a component never runs
+// into the next one and the concatenation compares like the tuple itself, first
+// component first. The keys sharing their first components are the ones whose
+// bytes start with the encoding of those components, which is what
+// `Radix::scan_prefix` looks for.
+macro_rules! impl_digital_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: Digital),+> Digital for ($($name,)+) {
+            type Encoded<'a> = KeyBuf where Self: 'a;
+            const DECODABLE: bool = $($name::DECODABLE)&&+;
+
+            #[allow(non_snake_case)]
+            fn encode(&self) -> Self::Encoded<'_> {
+                let ($(ref $name,)+) = *self;
+                let mut encoded = KeyBuf::new();
+                $(encoded.extend_from_slice($name.encode().as_ref());)+
+                encoded
+            }
+
+            #[allow(non_snake_case)]
+            fn encode_with<Sink: FnMut(&[u8]) -> bool>(&self, sink: &mut Sink) -> bool {
+                let ($(ref $name,)+) = *self;
+                $($name.encode_with(sink))&&+
+            }
+
+            #[allow(non_snake_case)]
+            fn decode(bytes: &[u8]) -> Option<(Self, &[u8])> {
+                $(let ($name, bytes) = $name::decode(bytes)?;)+
+                Some((($($name,)+), bytes))
+            }
         }
     };
 }
 
-pub trait Digital {
-    // TODO: consider providing a more efficient interface here (e.g. passing a slice directly)
-    type I: Iterator<Item = u8>;
-    const STOP_CHARACTER: Option<u8> = None;
-    fn digits(&self) -> Self::I;
+impl_digital_tuple!(A);
+impl_digital_tuple!(A B);
+impl_digital_tuple!(A B C);
+impl_digital_tuple!(A B C D);
+impl_digital_tuple!(A B C D E);
+impl_digital_tuple!(A B C D E F);
+impl_digital_tuple!(A B C D E F G);
+impl_digital_tuple!(A B C D E F G H);
+
+impl<T: Digital, const N: usize> Digital for [T; N] {
+    type Encoded<'a>
+        = KeyBuf
+    where
+        Self: 'a;
+    const DECODABLE: bool = T::DECODABLE;
+
+    fn encode(&self) -> Self::Encoded<'_> {
+        let mut encoded = KeyBuf::new();
+        for item in self.iter() {
+            encoded.extend_from_slice(item.encode().as_ref());
+        }
+        encoded
+    }
+
+    fn encode_with<F: FnMut(&[u8]) -> bool>(&self, sink: &mut F) -> bool {
+        self.iter().all(|item| item.encode_with(sink))
+    }
+
+    fn decode(mut bytes: &[u8]) -> Option<(Self, &[u8])> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            let (item, rest) = T::decode(bytes)?;
+            items.push(item);
+            bytes = rest;
+        }
+        let items: [T; N] = items.try_into().ok()?;
+        Some((items, bytes))
+    }
+}
+
+/// Copies the encoding of `key` into a `KeyBuf`, without building it first.
+pub fn encode_to_buf<K: Digital + ?Sized>(key: &K) -> KeyBuf {
+    let mut encoded = KeyBuf::new();
+    key.encode_with(&mut |piece| {
+        encoded.extend_from_slice(piece);
+        true
+    });
+    encoded
+}
+
+/// Where an encoded key first differs from other bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mismatch {
+    /// The number of bytes both start with.
+    pub len: usize,
+    /// The byte of the key after the common ones, `None` if the key ends there.
+    pub ours: Option<u8>,
+    /// The byte of the other bytes after the common ones, `None` if they end
+    /// there.
+    pub theirs: Option<u8>,
+}
+
+impl Mismatch {
+    pub fn of_slices(ours: &[u8], theirs: &[u8]) -> Self {
+        let len = ours.iter().zip(theirs).take_while(|&(a, b)| a == b).count();
+        Mismatch {
+            len,
+            ours: ours.get(len).cloned(),
+            theirs: theirs.get(len).cloned(),
+        }
+    }
+
+    /// Compares the encoding of `key` with `theirs`, as if the encoding were cut
+    /// to `len` bytes from `start` on. The encoding is streamed and never built.
+    pub fn of_encoding<K: Digital + ?Sized>(
+        key: &K,
+        start: usize,
+        len: usize,
+        theirs: &[u8],
+    ) -> Self {
+        let mut skip = start;
+        let mut matched = 0;
+        let mut ours = None;
+        key.encode_with(&mut |mut piece: &[u8]| {
+            if skip >= piece.len() {
+                skip -= piece.len();
+                return true;
+            }
+            piece = &piece[skip..];
+            skip = 0;
+            for byte in piece {
+                if matched == len {
+                    return false;
+                }
+                if theirs.get(matched) != Some(byte) {
+                    ours = Some(*byte);
+                    return false;
+                }
+                matched += 1;
+            }
+            true
+        });
+        Mismatch {
+            len: matched,
+            ours,
+            theirs: theirs.get(matched).cloned(),
+        }
+    }
+
+    /// Returns `true` if the key and the other bytes are the same.
+    pub fn is_equal(&self) -> bool {
+        self.ours.is_none() && self.theirs.is_none()
+    }
+
+    /// Orders the key relative to the other bytes. A key ending first sorts first.
+    pub fn ordering(&self) -> Ordering {
+        self.ours.cmp(&self.theirs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_to_buf, Digital, KeyBuf, Mismatch};
+    use std::cmp::Ordering;
+    use std::fmt::Debug;
+
+    fn encoded<T: Digital>(value: &T) -> Vec<u8> {
+        value.encode().as_ref().to_vec()
+    }
+
+    fn assert_ordered<T: Digital + Copy + PartialOrd>(values: &[T]) {
+        for pair in values.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(pair[0].encode().as_ref() < pair[1].encode().as_ref());
+        }
+    }
+
+    fn assert_round_trip<T: Digital + PartialEq + Debug>(values: &[T]) {
+        for value in values {
+            let bytes = encoded(value);
+            assert_eq!(T::from_bytes(&bytes).as_ref(), Some(value));
+            assert!(T::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        }
+    }
+
+    #[test]
+    fn unsigned_integers_keep_their_order() {
+        assert_ordered(&[0u8, 1, 127, 128, 255]);
+        assert_ordered(&[0u16, 1, 255, 256, 65_535]);
+        assert_ordered(&[0u32, 1, 255, 256, 65_536, u32::MAX]);
+        assert_ordered(&[0u64, 1, 256, 1 << 32, u64::MAX]);
+        assert_ordered(&[0u128, 1, 1 << 64, u128::MAX]);
+        assert_ordered(&[0usize, 1, 256, usize::MAX]);
+        assert_eq!(encoded(&0x0102_0304u32), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn signed_integers_keep_their_order() {
+        assert_ordered(&[i8::MIN, -1, 0, 1, i8::MAX]);
+        assert_ordered(&[i16::MIN, -256, -1, 0, 1, 256, i16::MAX]);
+        assert_ordered(&[i32::MIN, -65_536, -1, 0, 1, i32::MAX]);
+        assert_ordered(&[i64::MIN, -1, 0, 1, i64::MAX]);
+        assert_ordered(&[i128::MIN, -1, 0, 1, i128::MAX]);
+        assert_ordered(&[isize::MIN, -1, 0, 1, isize::MAX]);
+        assert_eq!(encoded(&-1i16), vec![0x7f, 0xff]);
+        assert_eq!(encoded(&0i16), vec![0x80, 0x00]);
+    }
+
+    #[test]
+    fn floats_keep_their_order() {
+        use std::{f32, f64};
+        assert_ordered(&[
+            f32::NEG_INFINITY,
+            f32::MIN,
+            -1.0,
+            -f32::MIN_POSITIVE,
+            0.0,
+            f32::MIN_POSITIVE,
+            1.0,
+            f32::MAX,
+            f32::INFINITY,
+        ]);
+        assert_ordered(&[
+            f64::NEG_INFINITY,
+            -1e300,
+            -1.5,
+            -0.0,
+            1e-300,
+            2.5,
+            f64::INFINITY,
+        ]);
+        // Not ordered by `PartialOrd`, so these are checked on the encoding alone.
+        let bytes = |x: f64| encoded(&x);
+        assert!(bytes(-0.0) < bytes(0.0));
+        assert!(bytes(-f64::NAN) < bytes(f64::NEG_INFINITY));
+        assert!(bytes(f64::INFINITY) < bytes(f64::NAN));
+        assert_eq!(encoded(&1.0f32), vec![0xbf, 0x80, 0, 0]);
+    }
+
+    #[test]
+    fn tuples_and_arrays_keep_their_order() {
+        assert_ordered(&[(0u8, -1i32), (0, 0), (0, 1), (1, i32::MIN), (1, 0)]);
+        assert_ordered(&[
+            (1u32, "", 5i64),
+            (1, "", 6),
+            (1, "a", -1),
+            (1, "a\0", -2),
+            (1, "ab", -3),
+            (2, "", 0),
+        ]);
+        assert_ordered(&[[0u16, 5], [0, 256], [1, 0], [256, 0]]);
+        assert_eq!(encoded(&(1u8, "a", 2u8)), vec![1, b'a', 0, 0, 2]);
+        assert_eq!(encoded(&[1u8, 2, 3]), vec![1, 2, 3]);
+
+        let prefix = encoded(&(7u32, "bob"));
+        assert!(encoded(&(7u32, "bob", 3i64)).starts_with(&prefix));
+        assert!(!encoded(&(7u32, "bobby", 3i64)).starts_with(&prefix));
+    }
+
+    #[test]
+    fn strings_are_prefix_free_and_ordered() {
+        assert_ordered(&[
+            "", "\0", "\0\0", "\x01", "a", "a\0", "a\0b", "a\x01", "ab", "b",
+        ]);
+        assert_ordered(&[&[][..], &[0], &[0, 0xff], &[1], &[0xff, 0], &[0xff, 0xff]]);
+        assert_eq!(encoded(&"a\0b"), vec![b'a', 0, 0xff, b'b', 0, 0]);
+        assert_eq!(encoded(&"foo".to_string()), encoded(&b"foo".to_vec()));
+    }
+
+    fn streamed<T: Digital>(value: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        value.encode_with(&mut |piece| {
+            bytes.extend_from_slice(piece);
+            true
+        });
+        bytes
+    }
+
+    #[test]
+    fn streamed_encodings_match_built_ones() {
+        assert_eq!(streamed(&-7i32), encoded(&-7i32));
+        assert_eq!(streamed(&"a\0\0b"), encoded(&"a\0\0b"));
+        assert_eq!(streamed(&vec![0u8, 1, 0]), encoded(&vec![0u8, 1, 0]));
+        let long = "x".repeat(40);
+        assert_eq!(
+            streamed(&(3u8, long.as_str(), "")),
+            encoded(&(3u8, long.as_str(), ""))
+        );
+        assert_eq!(streamed(&["\0", "y"]), encoded(&["\0", "y"]));
+        assert_eq!(
+            encode_to_buf(&(1u16, long.as_str())).as_ref(),
+            &encoded(&(1u16, long.as_str()))[..]
+        );
+
+        // A sink that stops early cuts the encoding short.
+        let mut pieces = 0;
+        assert!(!(1u8, "a", 2u8).encode_with(&mut |_| {
+            pieces += 1;
+            false
+        }));
+        assert_eq!(pieces, 1);
+    }
+
+    #[test]
+    fn mismatches_compare_encodings() {
+        let key = "abc";
+        let mismatch = Mismatch::of_encoding(&key, 0, usize::MAX, &encoded(&key));
+        assert!(mismatch.is_equal());
+        let mismatch = Mismatch::of_encoding(&key, 0, usize::MAX, b"abd");
+        assert_eq!(
+            (mismatch.len, mismatch.ours, mismatch.theirs),
+            (2, Some(b'c'), Some(b'd'))
+        );
+        assert_eq!(mismatch.ordering(), Ordering::Less);
+        let mismatch = Mismatch::of_encoding(&key, 0, usize::MAX, b"ab");
+        assert_eq!(mismatch.ordering(), Ordering::Greater);
+
+        // A window of the encoding runs out of bytes where it ends.
+        let mismatch = Mismatch::of_encoding(&key, 1, 2, b"bcz");
+        assert_eq!(
+            (mismatch.len, mismatch.ours, mismatch.theirs),
+            (2, None, Some(b'z'))
+        );
+        let mismatch = Mismatch::of_encoding(&key, 1, 2, b"bx");
+        assert_eq!(
+            (mismatch.len, mismatch.ours, mismatch.theirs),
+            (1, Some(b'c'), Some(b'x'))
+        );
+        assert_eq!(
+            Mismatch::of_slices(b"ab", b"abc"),
+            Mismatch {
+                len: 2,
+                ours: None,
+                theirs: Some(b'c'),
+            }
+        );
+    }
+
+    #[test]
+    fn key_buf_moves_to_the_heap_when_full() {
+        let mut buf = KeyBuf::new();
+        let bytes: Vec<u8> = (0..100).collect();
+        buf.extend_from_slice(&bytes[..32]);
+        assert_eq!(buf.as_ref(), &bytes[..32]);
+        buf.extend_from_slice(&bytes[32..]);
+        assert_eq!(buf.as_ref(), &bytes[..]);
+    }
+
+    #[test]
+    fn keys_decode_from_their_encoding() {
+        use std::f64;
+        assert_round_trip(&[0u8, 255]);
+        assert_round_trip(&[u128::MAX, 1 << 100]);
+        assert_round_trip(&[i16::MIN, -1, 0, i16::MAX]);
+        assert_round_trip(&[isize::MIN, 7]);
+        assert_round_trip(&[f64::NEG_INFINITY, -1.5, -0.0, 0.0, 2.5, f64::MAX]);
+        assert!(f64::from_bytes(&encoded(&f64::NAN)).unwrap().is_nan());
+        assert!(f64::from_bytes(&encoded(&-0.0)).unwrap().is_sign_negative());
+        assert_round_trip(&[String::new(), "a\0b".to_string(), "\0\0".to_string()]);
+        assert_round_trip(&[vec![0u8, 0xff, 0], vec![]]);
+        assert_round_trip(&[(7u32, "bob".to_string(), -3i64)]);
+        assert_round_trip(&[[1u16, 2, 3]]);
+        assert_round_trip(&[["x".to_string(), String::new()]]);
 
-    fn to_le_bytes(&self) -> Vec<u8>;
+        assert_eq!(<(u8, u8)>::decode(&[1, 2, 3]), Some(((1, 2), &[3][..])));
+        assert_eq!(u16::from_bytes(&[1, 2, 3]), None);
+        assert_eq!(String::from_bytes(&[b'a', 0, 1]), None);
+        assert_eq!(String::from_bytes(&[0xff, 0, 0]), None);
+        assert_eq!(<&str>::from_bytes(&encoded(&"a")), None);
+    }
 }
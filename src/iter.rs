@@ -0,0 +1,543 @@
+use epoch::Guard;
+use error::{retry, Restart};
+use internal::Digital;
+use node::ArtNodeRef;
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+use value::ValueRef;
+
+/// An inner node on a path, the version it was read at and the key byte where the
+/// walk continues.
+///
+/// Walking forward, `next` is the smallest key byte whose child has not been
+/// visited yet. Walking backward, every child below `next` is still to be visited.
+struct Frame<'g, K, V>
+where
+    K: 'g + Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    node: ArtNodeRef<'g, K, V>,
+    version: u64,
+    next: usize,
+}
+
+/// Walks the leaves between two bounds from both ends in key byte order.
+///
+/// Writers are never blocked by the walk. Whenever one of them modifies a node on
+/// one of the paths, that path is rebuilt from the last key returned on its side,
+/// so every key that stays in the tree is returned exactly once.
+///
+/// `start` and `end` move inwards as keys are returned, which is also how both
+/// ends notice that they met. A `Cursor` walks one end at a time and resets the
+/// walk whenever it turns around.
+pub(crate) struct RawIter<'g, K, V>
+where
+    K: 'g + Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    root: ArtNodeRef<'g, K, V>,
+    _guard: &'g Guard,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    front: Vec<Frame<'g, K, V>>,
+    back: Vec<Frame<'g, K, V>>,
+    front_started: bool,
+    back_started: bool,
+    finished: bool,
+}
+
+impl<'g, K, V> RawIter<'g, K, V>
+where
+    K: 'g + Default + Clone + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    pub(crate) fn new(
+        root: ArtNodeRef<'g, K, V>,
+        guard: &'g Guard,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+    ) -> Self {
+        RawIter {
+            root,
+            _guard: guard,
+            start,
+            end,
+            front: Vec::new(),
+            back: Vec::new(),
+            front_started: false,
+            back_started: false,
+            finished: false,
+        }
+    }
+
+    /// Rebuilds the front path from `start`.
+    fn seek_front(&mut self) {
+        retry(|| {
+            self.front.clear();
+            let start = self.start.clone();
+            self.seek_front_rec(as_slice(&start))
+        })
+    }
+
+    /// Pushes the path to the first leaf past `bound`.
+    fn seek_front_rec(&mut self, bound: Bound<&[u8]>) -> Result<(), Restart> {
+        let (key, inclusive) = match bound {
+            Bound::Included(key) => (key, true),
+            Bound::Excluded(key) => (key, false),
+            Bound::Unbounded => {
+                let version = self.root.header().unwrap().lock.read_lock()?;
+                self.front.push(Frame {
+                    node: self.root,
+                    version,
+                    next: 0,
+                });
+                return Ok(());
+            }
+        };
+        let mut node = self.root;
+        let mut depth = 0;
+        loop {
+            let header = node.header().unwrap();
+            let version = match self.front.last() {
+                Some(parent) => parent.node.read_child(parent.version, node)?,
+                None => header.lock.read_lock()?,
+            };
+            let mismatch = node.prefix_mismatch(key, depth)?;
+            if mismatch.ours.is_some() {
+                // `key` leaves the compressed path here: the whole subtree sorts
+                // either before or after it.
+                let after = mismatch.ordering() == Ordering::Greater;
+                header.lock.check(version)?;
+                if after {
+                    self.front.push(Frame {
+                        node,
+                        version,
+                        next: 0,
+                    });
+                }
+                return Ok(());
+            }
+            depth += mismatch.len;
+            let byte = match key.get(depth) {
+                Some(byte) => *byte,
+                None => {
+                    // Every key below extends `key` and thus sorts after it.
+                    header.lock.check(version)?;
+                    self.front.push(Frame {
+                        node,
+                        version,
+                        next: 0,
+                    });
+                    return Ok(());
+                }
+            };
+            let child = node.find_child(byte, version)?;
+            self.front.push(Frame {
+                node,
+                version,
+                next: byte as usize + 1,
+            });
+            match child {
+                None => return Ok(()),
+                Some(leaf) if leaf.is_leaf() => {
+                    let after = match leaf.leaf_mismatch(&key[..depth + 1], key).ordering() {
+                        Ordering::Greater => true,
+                        Ordering::Equal => inclusive,
+                        Ordering::Less => false,
+                    };
+                    if after {
+                        self.front.last_mut().unwrap().next = byte as usize;
+                    }
+                    return Ok(());
+                }
+                Some(child) => {
+                    node = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Rebuilds the back path from `end`.
+    fn seek_back(&mut self) {
+        retry(|| {
+            self.back.clear();
+            let end = self.end.clone();
+            self.seek_back_rec(as_slice(&end))
+        })
+    }
+
+    /// Pushes the path to the last leaf before `bound`.
+    fn seek_back_rec(&mut self, bound: Bound<&[u8]>) -> Result<(), Restart> {
+        let (key, inclusive) = match bound {
+            Bound::Included(key) => (key, true),
+            Bound::Excluded(key) => (key, false),
+            Bound::Unbounded => {
+                let version = self.root.header().unwrap().lock.read_lock()?;
+                self.back.push(Frame {
+                    node: self.root,
+                    version,
+                    next: 256,
+                });
+                return Ok(());
+            }
+        };
+        let mut node = self.root;
+        let mut depth = 0;
+        loop {
+            let header = node.header().unwrap();
+            let version = match self.back.last() {
+                Some(parent) => parent.node.read_child(parent.version, node)?,
+                None => header.lock.read_lock()?,
+            };
+            let mismatch = node.prefix_mismatch(key, depth)?;
+            if mismatch.ours.is_some() {
+                let before = mismatch.ordering() == Ordering::Less;
+                header.lock.check(version)?;
+                if before {
+                    self.back.push(Frame {
+                        node,
+                        version,
+                        next: 256,
+                    });
+                }
+                return Ok(());
+            }
+            depth += mismatch.len;
+            let byte = match key.get(depth) {
+                Some(byte) => *byte,
+                None => {
+                    // Every key below extends `key` and thus sorts after it.
+                    header.lock.check(version)?;
+                    return Ok(());
+                }
+            };
+            let child = node.find_child(byte, version)?;
+            self.back.push(Frame {
+                node,
+                version,
+                next: byte as usize,
+            });
+            match child {
+                None => return Ok(()),
+                Some(leaf) if leaf.is_leaf() => {
+                    let before = match leaf.leaf_mismatch(&key[..depth + 1], key).ordering() {
+                        Ordering::Less => true,
+                        Ordering::Equal => inclusive,
+                        Ordering::Greater => false,
+                    };
+                    if before {
+                        self.back.last_mut().unwrap().next = byte as usize + 1;
+                    }
+                    return Ok(());
+                }
+                Some(child) => {
+                    node = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Starts the walk over between `start` and `end`.
+    pub(crate) fn reset(&mut self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) {
+        self.start = start;
+        self.end = end;
+        self.front.clear();
+        self.back.clear();
+        self.front_started = false;
+        self.back_started = false;
+        self.finished = false;
+    }
+
+    pub(crate) fn next(&mut self) -> Option<(K, ValueRef<'g, V>)> {
+        if self.finished {
+            return None;
+        }
+        if !self.front_started {
+            self.front_started = true;
+            self.seek_front();
+        }
+        loop {
+            let (node, version, from) = match self.front.last() {
+                Some(frame) => (frame.node, frame.version, frame.next),
+                None => {
+                    self.finished = true;
+                    return None;
+                }
+            };
+            let (byte, child) = match node.next_child(from, version) {
+                Ok(Some(child)) => child,
+                Err(_) => {
+                    self.seek_front();
+                    continue;
+                }
+                Ok(None) => {
+                    self.front.pop();
+                    continue;
+                }
+            };
+            self.front.last_mut().unwrap().next = byte as usize + 1;
+            match child {
+                leaf if leaf.is_leaf() => {
+                    let path = match path_to(&self.front, leaf, true) {
+                        Ok(path) => path,
+                        Err(_) => {
+                            self.seek_front();
+                            continue;
+                        }
+                    };
+                    let key = leaf.with_leaf_key(&path, |key| key.to_vec());
+                    let past_end = match self.end {
+                        Bound::Included(ref end) => key > *end,
+                        Bound::Excluded(ref end) => key >= *end,
+                        Bound::Unbounded => false,
+                    };
+                    if past_end {
+                        self.finish();
+                        return None;
+                    }
+                    let entry = (leaf.leaf_key(&key), leaf.leaf_value());
+                    self.start = Bound::Excluded(key);
+                    return Some(entry);
+                }
+                _ => match node.read_child(version, child) {
+                    Ok(version) => self.front.push(Frame {
+                        node: child,
+                        version,
+                        next: 0,
+                    }),
+                    Err(_) => self.seek_front(),
+                },
+            }
+        }
+    }
+
+    pub(crate) fn next_back(&mut self) -> Option<(K, ValueRef<'g, V>)> {
+        if self.finished {
+            return None;
+        }
+        if !self.back_started {
+            self.back_started = true;
+            self.seek_back();
+        }
+        loop {
+            let (node, version, before) = match self.back.last() {
+                Some(frame) => (frame.node, frame.version, frame.next),
+                None => {
+                    self.finished = true;
+                    return None;
+                }
+            };
+            let (byte, child) = match node.prev_child(before, version) {
+                Ok(Some(child)) => child,
+                Err(_) => {
+                    self.seek_back();
+                    continue;
+                }
+                Ok(None) => {
+                    self.back.pop();
+                    continue;
+                }
+            };
+            self.back.last_mut().unwrap().next = byte as usize;
+            match child {
+                leaf if leaf.is_leaf() => {
+                    let path = match path_to(&self.back, leaf, false) {
+                        Ok(path) => path,
+                        Err(_) => {
+                            self.seek_back();
+                            continue;
+                        }
+                    };
+                    let key = leaf.with_leaf_key(&path, |key| key.to_vec());
+                    let past_start = match self.start {
+                        Bound::Included(ref start) => key < *start,
+                        Bound::Excluded(ref start) => key <= *start,
+                        Bound::Unbounded => false,
+                    };
+                    if past_start {
+                        self.finish();
+                        return None;
+                    }
+                    let entry = (leaf.leaf_key(&key), leaf.leaf_value());
+                    self.end = Bound::Excluded(key);
+                    return Some(entry);
+                }
+                _ => match node.read_child(version, child) {
+                    Ok(version) => self.back.push(Frame {
+                        node: child,
+                        version,
+                        next: 256,
+                    }),
+                    Err(_) => self.seek_back(),
+                },
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+        self.front.clear();
+        self.back.clear();
+    }
+}
+
+/// An iterator over all entries of a `Radix` in ascending key byte order.
+///
+/// Created by `Radix::iter`.
+pub struct Iter<'g, K, V>
+where
+    K: 'g + Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    inner: RawIter<'g, K, V>,
+}
+
+impl<'g, K, V> Iter<'g, K, V>
+where
+    K: 'g + Default + Clone + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    pub(crate) fn new(root: ArtNodeRef<'g, K, V>, guard: &'g Guard) -> Self {
+        Iter {
+            inner: RawIter::new(root, guard, Bound::Unbounded, Bound::Unbounded),
+        }
+    }
+}
+
+impl<'g, K, V> Iterator for Iter<'g, K, V>
+where
+    K: 'g + Default + Clone + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    type Item = (K, ValueRef<'g, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl<'g, K, V> DoubleEndedIterator for Iter<'g, K, V>
+where
+    K: 'g + Default + Clone + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// An iterator over the entries of a `Radix` within a range of keys, in ascending
+/// key byte order.
+///
+/// Created by `Radix::range`.
+pub struct Range<'g, K, V>
+where
+    K: 'g + Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    inner: RawIter<'g, K, V>,
+}
+
+impl<'g, K, V> Range<'g, K, V>
+where
+    K: 'g + Default + Clone + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    pub(crate) fn new<R: RangeBounds<K>>(
+        root: ArtNodeRef<'g, K, V>,
+        guard: &'g Guard,
+        range: R,
+    ) -> Self {
+        let start = to_bytes(range.start_bound());
+        let end = to_bytes(range.end_bound());
+        Range {
+            inner: RawIter::new(root, guard, start, end),
+        }
+    }
+
+    /// Covers exactly the keys starting with `prefix`, which is the range from
+    /// `prefix` itself up to the first byte string past all of its extensions.
+    pub(crate) fn prefix(root: ArtNodeRef<'g, K, V>, guard: &'g Guard, prefix: &[u8]) -> Self {
+        let mut end = prefix.to_vec();
+        while end.last() == Some(&0xff) {
+            end.pop();
+        }
+        let end = match end.pop() {
+            Some(byte) => {
+                end.push(byte + 1);
+                Bound::Excluded(end)
+            }
+            None => Bound::Unbounded,
+        };
+        Range {
+            inner: RawIter::new(root, guard, Bound::Included(prefix.to_vec()), end),
+        }
+    }
+}
+
+impl<'g, K, V> Iterator for Range<'g, K, V>
+where
+    K: 'g + Default + Clone + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    type Item = (K, ValueRef<'g, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl<'g, K, V> DoubleEndedIterator for Range<'g, K, V>
+where
+    K: 'g + Default + Clone + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// Returns the key bytes leading to `leaf`, the child of the last frame, if it is
+/// a small leaf and thus needs them for its key.
+///
+/// Every frame lies on the path, so its prefix is read and validated again rather
+/// than trusted from when the frame was pushed.
+fn path_to<'g, K, V>(
+    frames: &[Frame<'g, K, V>],
+    leaf: ArtNodeRef<'g, K, V>,
+    forward: bool,
+) -> Result<Vec<u8>, Restart>
+where
+    K: 'g + Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    let mut path = Vec::new();
+    if let ArtNodeRef::LeafSmall(_) = leaf {
+        for frame in frames {
+            let header = frame.node.header().unwrap();
+            path.extend_from_slice(&header.get_partial());
+            header.lock.check(frame.version)?;
+            // Walking forward, `next` already points past the child on the path.
+            path.push(if forward { frame.next - 1 } else { frame.next } as u8);
+        }
+    }
+    Ok(path)
+}
+
+pub(crate) fn to_bytes<K: Digital>(bound: Bound<&K>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.encode().as_ref().to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(key.encode().as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn as_slice(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match *bound {
+        Bound::Included(ref key) => Bound::Included(key),
+        Bound::Excluded(ref key) => Bound::Excluded(key),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
@@ -1,26 +1,33 @@
-#![feature(core_intrinsics)]
-#![feature(extern_prelude)]
-#![feature(integer_atomics)]
-#![feature(int_to_from_bytes)]
+extern crate arrayvec;
 extern crate crossbeam_epoch;
 extern crate crossbeam_utils;
-#[cfg(feature = "use_std")]
-#[macro_use]
-extern crate lazy_static;
-#[macro_use]
-extern crate memoffset;
+#[cfg(loom)]
+extern crate loom;
+#[cfg(feature = "derive")]
+extern crate radix_tree_derive;
 #[macro_use]
 extern crate scopeguard;
 
-#[macro_use]
 mod internal;
+mod cursor;
 mod epoch;
+mod error;
+mod iter;
+mod lock;
 mod node;
 mod node16;
 mod node256;
 mod node4;
 mod node48;
 mod tree;
+mod value;
 
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+pub use cursor::Cursor;
+pub use epoch::{Guard, Handle};
+pub use error::RadixError;
+pub use internal::{Digital, KeyBuf};
+pub use iter::{Iter, Range};
+#[cfg(feature = "derive")]
+pub use radix_tree_derive::Digital;
+pub use tree::Radix;
+pub use value::ValueRef;
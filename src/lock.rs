@@ -0,0 +1,250 @@
+//! The optimistic lock coupling (OLC) lock of a node.
+//!
+//! The lock is a single word. Bit 0 marks the node obsolete, bit 1 is set while
+//! a writer holds the lock and the bits above count completed writes:
+//!
+//! - Readers take no lock. They remember the version, read the node and then
+//!   `check` that the version did not change, restarting if it did.
+//! - Writers `upgrade` a version they read to the write lock, which fails if
+//!   anyone else wrote or locked in the meantime.
+//! - `write_unlock` clears the lock bit and bumps the version, so every reader
+//!   that overlapped the write fails its check.
+//! - `write_unlock_obsolete` also sets the obsolete bit for a node that was
+//!   unlinked. An obsolete lock can never be taken again.
+//!
+//! Node contents are atomics read with relaxed ordering, and the fences below
+//! order them against the version like a seqlock. The tests model-check the
+//! protocol with loom:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib lock::
+//! ```
+
+use error::Restart;
+
+#[cfg(loom)]
+use loom::hint;
+#[cfg(loom)]
+use loom::sync::atomic::{fence, AtomicU64, Ordering};
+#[cfg(not(loom))]
+use std::hint;
+#[cfg(not(loom))]
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+const OBSOLETE: u64 = 0b01;
+const LOCKED: u64 = 0b10;
+
+pub struct VersionLock {
+    version: AtomicU64,
+}
+
+impl VersionLock {
+    pub fn new() -> Self {
+        VersionLock {
+            version: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the current version, unless the node is locked or obsolete.
+    pub fn read_lock(&self) -> Result<u64, Restart> {
+        let version = self.version.load(Ordering::Acquire);
+        if version & (LOCKED | OBSOLETE) != 0 {
+            return Err(Restart);
+        }
+        Ok(version)
+    }
+
+    /// Fails if the node changed since `version` was read.
+    ///
+    /// Everything read from the node before the check is valid if it succeeds.
+    #[inline]
+    pub fn check(&self, version: u64) -> Result<(), Restart> {
+        fence(Ordering::Acquire);
+        if self.version.load(Ordering::Relaxed) != version {
+            return Err(Restart);
+        }
+        Ok(())
+    }
+
+    /// Takes the write lock if the node is still at `version`.
+    pub fn upgrade(&self, version: u64) -> Result<(), Restart> {
+        match self.version.compare_exchange(
+            version,
+            version + LOCKED,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => {
+                // Readers that see any of the writes to come also see the lock.
+                fence(Ordering::Release);
+                Ok(())
+            }
+            Err(_) => Err(Restart),
+        }
+    }
+
+    /// Like `check`, but releases `other` when it fails.
+    pub fn check_or_unlock(&self, version: u64, other: &VersionLock) -> Result<(), Restart> {
+        self.check(version).inspect_err(|_| other.write_unlock())
+    }
+
+    /// Like `upgrade`, but releases `other` when it fails.
+    pub fn upgrade_or_unlock(&self, version: u64, other: &VersionLock) -> Result<(), Restart> {
+        self.upgrade(version).inspect_err(|_| other.write_unlock())
+    }
+
+    /// Waits for the write lock, failing only once the node is obsolete.
+    pub fn write_lock(&self) -> Result<(), Restart> {
+        loop {
+            let version = self.version.load(Ordering::Relaxed);
+            if version & OBSOLETE != 0 {
+                return Err(Restart);
+            }
+            if version & LOCKED != 0 {
+                hint::spin_loop();
+                continue;
+            }
+            if self.upgrade(version).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn write_unlock(&self) {
+        debug_assert!(self.is_locked(), "unlocking a node that is not locked");
+        self.version.fetch_add(LOCKED, Ordering::Release);
+    }
+
+    /// Releases the lock of an unlinked node for good.
+    pub fn write_unlock_obsolete(&self) {
+        debug_assert!(self.is_locked(), "unlocking a node that is not locked");
+        self.version.fetch_add(LOCKED | OBSOLETE, Ordering::Release);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.version.load(Ordering::Relaxed) & LOCKED != 0
+    }
+}
+
+#[cfg(not(loom))]
+#[test]
+fn lock() {
+    let lock = VersionLock::new();
+    assert!(!lock.is_locked());
+    assert!(lock.write_lock().is_ok());
+    assert!(lock.is_locked());
+    lock.write_unlock();
+    assert!(!lock.is_locked());
+}
+
+#[cfg(not(loom))]
+#[test]
+fn upgrade_lock() {
+    let lock = VersionLock::new();
+    let version = lock.read_lock().unwrap();
+    assert!(lock.upgrade(version).is_ok());
+    assert!(lock.read_lock().is_err());
+    lock.write_unlock();
+    assert!(lock.check(version).is_err());
+    assert!(lock.upgrade(version).is_err());
+
+    let version = lock.read_lock().unwrap();
+    lock.upgrade(version).unwrap();
+    lock.write_unlock_obsolete();
+    assert!(lock.read_lock().is_err());
+    assert!(lock.write_lock().is_err());
+    assert!(!lock.is_locked());
+}
+
+#[cfg(loom)]
+mod tests {
+    use super::VersionLock;
+    use loom::sync::atomic::{AtomicU8, Ordering};
+    use loom::sync::Arc;
+    use loom::thread;
+
+    #[test]
+    fn one_writer_wins_an_upgrade() {
+        loom::model(|| {
+            let lock = Arc::new(VersionLock::new());
+            let version = lock.read_lock().unwrap();
+            let other = {
+                let lock = lock.clone();
+                thread::spawn(move || lock.upgrade(version).is_ok())
+            };
+            let mine = lock.upgrade(version).is_ok();
+            let theirs = other.join().unwrap();
+            assert!(mine != theirs);
+            assert!(lock.is_locked());
+        });
+    }
+
+    #[test]
+    fn validated_reads_are_consistent() {
+        loom::model(|| {
+            // A writer moves a pair from (0, 0) to (1, 1). A reader whose check
+            // succeeds must not see it halfway.
+            let lock = Arc::new(VersionLock::new());
+            let pair = Arc::new((AtomicU8::new(0), AtomicU8::new(0)));
+            let writer = {
+                let (lock, pair) = (lock.clone(), pair.clone());
+                thread::spawn(move || {
+                    lock.write_lock().unwrap();
+                    pair.0.store(1, Ordering::Relaxed);
+                    pair.1.store(1, Ordering::Relaxed);
+                    lock.write_unlock();
+                })
+            };
+            if let Ok(version) = lock.read_lock() {
+                let first = pair.0.load(Ordering::Relaxed);
+                let second = pair.1.load(Ordering::Relaxed);
+                if lock.check(version).is_ok() {
+                    assert_eq!(first, second);
+                }
+            }
+            writer.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn waiting_writers_see_obsolete_nodes() {
+        loom::model(|| {
+            let lock = Arc::new(VersionLock::new());
+            lock.write_lock().unwrap();
+            let waiter = {
+                let lock = lock.clone();
+                thread::spawn(move || lock.write_lock().is_ok())
+            };
+            lock.write_unlock_obsolete();
+            assert!(!waiter.join().unwrap());
+            assert!(lock.read_lock().is_err());
+        });
+    }
+
+    #[test]
+    fn writers_exclude_each_other() {
+        loom::model(|| {
+            // Both writers increment through a read and a separate store, which only
+            // adds up if they never overlap.
+            let lock = Arc::new(VersionLock::new());
+            let counter = Arc::new(AtomicU8::new(0));
+            let writers: Vec<_> = (0..2)
+                .map(|_| {
+                    let (lock, counter) = (lock.clone(), counter.clone());
+                    thread::spawn(move || {
+                        lock.write_lock().unwrap();
+                        let n = counter.load(Ordering::Relaxed);
+                        counter.store(n + 1, Ordering::Relaxed);
+                        lock.write_unlock();
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            assert_eq!(counter.load(Ordering::Relaxed), 2);
+            // Taking and releasing the lock both count, so each write adds 4.
+            assert_eq!(lock.read_lock(), Ok(8));
+        });
+    }
+}
@@ -1,21 +1,24 @@
-use internal::Digital;
+use arrayvec::ArrayVec;
+use error::Restart;
+use internal::{Digital, Mismatch};
+use lock::VersionLock;
 use node16::Node16;
 use node256::Node256;
 use node4::Node4;
 use node48::Node48;
-use std::arch::x86_64::_mm_pause;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::{mem, ptr};
-
-const MAX_PREFIX_LEN: usize = 6;
-
-enum NodeType {
-    NODE4,
-    NODE16,
-    NODE48,
-    NODE256,
-}
+use std::any::TypeId;
+use std::array;
+use std::cmp;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicPtr, AtomicU16, AtomicU8, AtomicUsize, Ordering};
+use value::ValueRef;
+
+/// Number of prefix bytes an inner node stores. The rest of a longer prefix is
+/// skipped while descending and checked against the key in the leaf.
+pub const MAX_PREFIX_LEN: usize = 6;
 
 pub enum ArtNode<K, V>
 where
@@ -24,190 +27,775 @@ where
 {
     Empty,
 
-    Inner4(Box<Node4<K, V>>),
-    Inner16(Box<Node16<K, V>>),
-    Inner48(Box<Node48<K, V>>),
-    Inner256(Box<Node256<K, V>>),
-    Value(usize),
-    //LeafLarge(Box<(K, V)>),
-    //LeafLargeKey(Box<K>, SmallStruct<V>),
-    //LeafLargeValue(SmallStruct<K>, Box<V>),
-    //LeafSmall(SmallStruct<K>, SmallStruct<V>)
+    Inner4(Owned<Node4<K, V>>),
+    Inner16(Owned<Node16<K, V>>),
+    Inner48(Owned<Node48<K, V>>),
+    Inner256(Owned<Node256<K, V>>),
+    LeafLarge(Owned<Leaf<K, V>>),
+    LeafSmall(SmallLeaf<V>),
 }
 
-pub trait ArtKey {
-    fn bytes(&self) -> &[u8];
+/// An owned heap allocation, like a `Box` that does not claim unique access.
+///
+/// Other threads keep reading a node while it moves from one child slot to
+/// another, so it is only turned back into a `Box` to be freed.
+pub struct Owned<T>(NonNull<T>);
+
+unsafe impl<T: Send> Send for Owned<T> {}
+unsafe impl<T: Sync> Sync for Owned<T> {}
+
+impl<T> Owned<T> {
+    pub fn new(value: T) -> Self {
+        Owned(NonNull::from(Box::leak(Box::new(value))))
+    }
+
+    fn into_raw(owned: Self) -> *mut T {
+        let ptr = owned.0.as_ptr();
+        mem::forget(owned);
+        ptr
+    }
+
+    unsafe fn from_raw(ptr: *mut T) -> Self {
+        Owned(NonNull::new_unchecked(ptr))
+    }
 }
 
-#[derive(Clone)]
-pub struct NodeHeader {
-    //NodeType: NodeType,
-    version: Arc<AtomicUsize>, // unlock 0, lock 1
-    pub num_children: u8,
-    partial: [u8; MAX_PREFIX_LEN],
-    partial_len: usize,
+impl<T> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.0.as_ref() }
+    }
 }
 
-pub fn is_locked(version: &Arc<AtomicUsize>) -> bool {
-    version.load(Ordering::SeqCst) & 0b10 == 0b10
+impl<T> Drop for Owned<T> {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(self.0.as_ptr())) }
+    }
 }
 
-pub fn is_obsolete(version: &Arc<AtomicUsize>) -> bool {
-    version.load(Ordering::SeqCst) & 1 == 1
+/// A key/value pair hanging below the last byte of its key.
+///
+/// Leaves are over-aligned so that the low bits of their address are free for the
+/// child slot tag.
+#[repr(align(8))]
+pub struct Leaf<K, V>(pub K, pub V);
+
+/// A value kept in the child slot itself.
+///
+/// The value fills the whole slot, so the slots of a node that hold small leaves
+/// are marked in a bitmap apart from them, see `Slots`. A small leaf has no key:
+/// it sits below the last byte of its key, so the key is the path leading to it.
+/// Trees keep a value this way when `fits_inline` accepts its type, their keys
+/// decode and the path to the leaf spells out its whole key.
+pub struct SmallLeaf<V> {
+    word: usize,
+    marker: PhantomData<V>,
 }
 
-impl NodeHeader {
-    pub fn new() -> Self {
-        NodeHeader {
-            version: Arc::new(AtomicUsize::new(0)),
-            num_children: 0,
-            partial_len: 0,
-            partial: unsafe { mem::uninitialized() },
+impl<V> SmallLeaf<V> {
+    pub fn new(value: V) -> Self {
+        debug_assert!(mem::size_of::<V>() <= mem::size_of::<usize>());
+        let mut bytes = [0u8; mem::size_of::<usize>()];
+        unsafe {
+            ptr::write_unaligned(bytes.as_mut_ptr() as *mut V, value);
+        }
+        SmallLeaf {
+            word: usize::from_ne_bytes(bytes),
+            marker: PhantomData,
         }
     }
 
-    pub fn get_partial_len(&self) -> usize {
-        self.partial_len
+    /// Returns a copy of the value.
+    pub fn get(&self) -> V {
+        let bytes = self.word.to_ne_bytes();
+        unsafe { ptr::read_unaligned(bytes.as_ptr() as *const V) }
     }
+}
 
-    #[inline]
-    pub fn read_version(&self) -> usize {
-        return self.version.load(Ordering::SeqCst);
+impl<V> Clone for SmallLeaf<V> {
+    fn clone(&self) -> Self {
+        *self
     }
+}
 
-    pub fn write_lock_or_restart(&self) -> bool {
-        loop {
-            let mut ver = self.version.load(Ordering::SeqCst);
-            while is_locked(&self.version) {
-                unsafe {
-                    _mm_pause();
-                    ver = self.version.load(Ordering::SeqCst);
-                }
+impl<V> Copy for SmallLeaf<V> {}
+
+/// Returns `true` if values of type `V` can be kept in small leaves.
+///
+/// These are the primitives that fit into a child slot: they have no padding, no
+/// drop glue and no interior mutability, so copying one out of its slot is as
+/// good as borrowing it. Everything else goes into a boxed `Leaf`.
+pub fn fits_inline<V: 'static>() -> bool {
+    let small = [
+        TypeId::of::<()>(),
+        TypeId::of::<bool>(),
+        TypeId::of::<char>(),
+        TypeId::of::<u8>(),
+        TypeId::of::<i8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<i16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<i32>(),
+        TypeId::of::<f32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<i64>(),
+        TypeId::of::<f64>(),
+        TypeId::of::<usize>(),
+        TypeId::of::<isize>(),
+    ];
+    mem::size_of::<V>() <= mem::size_of::<usize>() && small.contains(&TypeId::of::<V>())
+}
+
+// Child slots not holding a small leaf store a pointer whose low bits say what it
+// points to. The tag is set and cleared through `map_addr`, so the pointer keeps
+// its provenance.
+const TAG_MASK: usize = 0b111;
+const TAG_INNER4: usize = 1;
+const TAG_INNER16: usize = 2;
+const TAG_INNER48: usize = 3;
+const TAG_INNER256: usize = 4;
+const TAG_LEAF_LARGE: usize = 5;
+
+impl<K, V> ArtNode<K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    /// Returns the word of the slot holding the node, and whether it is a small
+    /// leaf.
+    fn into_slot(self) -> (*mut u8, bool) {
+        let (ptr, tag) = match self {
+            ArtNode::Empty => return (ptr::null_mut(), false),
+            ArtNode::Inner4(ptr) => (Owned::into_raw(ptr) as *mut u8, TAG_INNER4),
+            ArtNode::Inner16(ptr) => (Owned::into_raw(ptr) as *mut u8, TAG_INNER16),
+            ArtNode::Inner48(ptr) => (Owned::into_raw(ptr) as *mut u8, TAG_INNER48),
+            ArtNode::Inner256(ptr) => (Owned::into_raw(ptr) as *mut u8, TAG_INNER256),
+            ArtNode::LeafLarge(ptr) => (Owned::into_raw(ptr) as *mut u8, TAG_LEAF_LARGE),
+            // A small leaf points nowhere, its word is only ever read back.
+            ArtNode::LeafSmall(leaf) => return (ptr::without_provenance_mut(leaf.word), true),
+        };
+        debug_assert_eq!(ptr.addr() & TAG_MASK, 0, "unaligned node");
+        (ptr.map_addr(|addr| addr | tag), false)
+    }
+
+    unsafe fn from_slot(data: *mut u8, small: bool) -> Self {
+        if small {
+            return ArtNode::LeafSmall(SmallLeaf {
+                word: data.addr(),
+                marker: PhantomData,
+            });
+        }
+        let ptr = data.map_addr(|addr| addr & !TAG_MASK);
+        match data.addr() & TAG_MASK {
+            TAG_INNER4 => ArtNode::Inner4(Owned::from_raw(ptr.cast())),
+            TAG_INNER16 => ArtNode::Inner16(Owned::from_raw(ptr.cast())),
+            TAG_INNER48 => ArtNode::Inner48(Owned::from_raw(ptr.cast())),
+            TAG_INNER256 => ArtNode::Inner256(Owned::from_raw(ptr.cast())),
+            TAG_LEAF_LARGE => ArtNode::LeafLarge(Owned::from_raw(ptr.cast())),
+            _ => ArtNode::Empty,
+        }
+    }
+
+    pub fn as_ref(&self) -> ArtNodeRef<'_, K, V> {
+        match self {
+            ArtNode::Empty => ArtNodeRef::Empty,
+            ArtNode::Inner4(ptr) => ArtNodeRef::Inner4(ptr),
+            ArtNode::Inner16(ptr) => ArtNodeRef::Inner16(ptr),
+            ArtNode::Inner48(ptr) => ArtNodeRef::Inner48(ptr),
+            ArtNode::Inner256(ptr) => ArtNodeRef::Inner256(ptr),
+            ArtNode::LeafLarge(ptr) => ArtNodeRef::LeafLarge(ptr),
+            ArtNode::LeafSmall(leaf) => ArtNodeRef::LeafSmall(*leaf),
+        }
+    }
+}
+
+/// A child together with the key byte it is stored under.
+pub type KeyedChild<'a, K, V> = (u8, ArtNodeRef<'a, K, V>);
+
+/// A borrowed view of a child loaded from its slot, see `RawChild`.
+///
+/// Loading a child does not lock it, so everything read through the view must be
+/// validated against the version of the node it was loaded from.
+pub enum ArtNodeRef<'a, K, V>
+where
+    K: 'a + Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    Empty,
+    Inner4(&'a Node4<K, V>),
+    Inner16(&'a Node16<K, V>),
+    Inner48(&'a Node48<K, V>),
+    Inner256(&'a Node256<K, V>),
+    LeafLarge(&'a Leaf<K, V>),
+    LeafSmall(SmallLeaf<V>),
+}
+
+impl<'a, K, V> Clone for ArtNodeRef<'a, K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K, V> Copy for ArtNodeRef<'a, K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+}
+
+impl<'a, K, V> ArtNodeRef<'a, K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    unsafe fn from_slot(data: *mut u8, small: bool) -> Self {
+        if small {
+            return ArtNodeRef::LeafSmall(SmallLeaf {
+                word: data.addr(),
+                marker: PhantomData,
+            });
+        }
+        let ptr = data.map_addr(|addr| addr & !TAG_MASK);
+        match data.addr() & TAG_MASK {
+            TAG_INNER4 => ArtNodeRef::Inner4(&*ptr.cast()),
+            TAG_INNER16 => ArtNodeRef::Inner16(&*ptr.cast()),
+            TAG_INNER48 => ArtNodeRef::Inner48(&*ptr.cast()),
+            TAG_INNER256 => ArtNodeRef::Inner256(&*ptr.cast()),
+            TAG_LEAF_LARGE => ArtNodeRef::LeafLarge(&*ptr.cast()),
+            _ => ArtNodeRef::Empty,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        matches!(*self, ArtNodeRef::LeafLarge(_) | ArtNodeRef::LeafSmall(_))
+    }
+
+    /// Calls `f` with the encoded key of a leaf. `path` holds the key bytes leading
+    /// to the leaf, which are the whole key of a small leaf.
+    pub fn with_leaf_key<R, F: FnOnce(&[u8]) -> R>(&self, path: &[u8], f: F) -> R {
+        match *self {
+            ArtNodeRef::LeafLarge(leaf) => f(leaf.0.encode().as_ref()),
+            ArtNodeRef::LeafSmall(_) => f(path),
+            _ => unreachable!("only leaves have keys"),
+        }
+    }
+
+    /// Compares the encoded key of a leaf with `key`. `path` holds the key bytes
+    /// leading to the leaf, which are the whole key of a small leaf.
+    pub fn leaf_mismatch(&self, path: &[u8], key: &[u8]) -> Mismatch {
+        match *self {
+            ArtNodeRef::LeafLarge(leaf) => Mismatch::of_encoding(&leaf.0, 0, usize::MAX, key),
+            ArtNodeRef::LeafSmall(_) => Mismatch::of_slices(path, key),
+            _ => unreachable!("only leaves have keys"),
+        }
+    }
+
+    /// Returns the key of a leaf, decoding it from `path` for a small leaf.
+    pub fn leaf_key(&self, path: &[u8]) -> K
+    where
+        K: Clone,
+    {
+        match *self {
+            ArtNodeRef::LeafLarge(leaf) => leaf.0.clone(),
+            ArtNodeRef::LeafSmall(_) => {
+                K::from_bytes(path).expect("the path to a small leaf decodes to its key")
             }
+            _ => unreachable!("only leaves have keys"),
+        }
+    }
 
-            if is_obsolete(&self.version) {
+    pub fn leaf_value(&self) -> ValueRef<'a, V> {
+        match *self {
+            ArtNodeRef::LeafLarge(leaf) => ValueRef::borrowed(&leaf.1),
+            ArtNodeRef::LeafSmall(leaf) => ValueRef::copied(leaf.get()),
+            _ => unreachable!("only leaves have values"),
+        }
+    }
+
+    pub fn header(&self) -> Option<&'a NodeHeader> {
+        match *self {
+            ArtNodeRef::Inner4(ptr) => Some(&ptr.header),
+            ArtNodeRef::Inner16(ptr) => Some(&ptr.header),
+            ArtNodeRef::Inner48(ptr) => Some(&ptr.header),
+            ArtNodeRef::Inner256(ptr) => Some(&ptr.header),
+            _ => None,
+        }
+    }
+
+    /// Reads the version of `child`, an inner node found below this one at
+    /// `version`.
+    ///
+    /// This node is checked again afterwards, which proves that `child` was still
+    /// below it when its version was read, and thus where the caller expects it.
+    pub fn read_child(&self, version: u64, child: ArtNodeRef<'a, K, V>) -> Result<u64, Restart> {
+        let child_version = child.header().unwrap().lock.read_lock()?;
+        self.header().unwrap().lock.check(version)?;
+        Ok(child_version)
+    }
+
+    /// Compares the whole compressed prefix of an inner node, which starts at
+    /// `depth`, with `key[depth..]`. The mismatch runs out of bytes of ours where
+    /// the prefix ends.
+    ///
+    /// The bytes past the stored ones are compared with the key of a leaf below
+    /// the node, since every key in its subtree shares them. That key is streamed
+    /// and never encoded in full, see `Digital::encode_with`. The caller must
+    /// validate the version of the node afterwards.
+    pub fn prefix_mismatch(&self, key: &[u8], depth: usize) -> Result<Mismatch, Restart> {
+        let header = self.header().expect("only inner nodes have prefixes");
+        if header.is_prefix_complete() {
+            return Ok(Mismatch::of_slices(&header.get_partial(), &key[depth..]));
+        }
+        let len = header.get_partial_len();
+        let mismatch = Mismatch::of_encoding(self.any_leaf_key()?, depth, len, &key[depth..]);
+        if mismatch.ours.is_none() && mismatch.len < len {
+            // The leaf is too short, it was read while the node changed.
+            return Err(Restart);
+        }
+        Ok(mismatch)
+    }
+
+    /// Returns what is left of the compressed prefix of an inner node, which
+    /// starts at `depth`, once its first `skip` bytes are cut off: the bytes a
+    /// node keeps of it, and its length. The caller must validate the version of
+    /// the node afterwards.
+    pub fn prefix_tail(
+        &self,
+        depth: usize,
+        skip: usize,
+    ) -> Result<(ArrayVec<[u8; MAX_PREFIX_LEN]>, usize), Restart> {
+        let header = self.header().expect("only inner nodes have prefixes");
+        let len = match header.get_partial_len().checked_sub(skip) {
+            Some(len) => len,
+            None => return Err(Restart),
+        };
+        let mut tail = ArrayVec::new();
+        if header.is_prefix_complete() {
+            for byte in header.get_partial().iter().skip(skip) {
+                tail.push(*byte);
+            }
+            return Ok((tail, len));
+        }
+        let kept = cmp::min(len, MAX_PREFIX_LEN);
+        let mut skip = depth + skip;
+        self.any_leaf_key()?.encode_with(&mut |piece: &[u8]| {
+            if skip >= piece.len() {
+                skip -= piece.len();
                 return true;
             }
+            for byte in &piece[skip..] {
+                if tail.len() == kept {
+                    return false;
+                }
+                tail.push(*byte);
+            }
+            skip = 0;
+            tail.len() < kept
+        });
+        if tail.len() < kept {
+            return Err(Restart);
+        }
+        Ok((tail, len))
+    }
 
-            match self.version.compare_exchange_weak(
-                ver,
-                ver + 0b10,
-                Ordering::SeqCst,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => break,
-                Err(_) => continue,
+    /// Returns the key of some leaf below an inner node whose prefix is longer
+    /// than it stores.
+    fn any_leaf_key(&self) -> Result<&'a K, Restart> {
+        let mut node = *self;
+        loop {
+            match node {
+                ArtNodeRef::LeafLarge(leaf) => return Ok(&leaf.0),
+                // Prefixes above small leaves are stored in full, so the node was
+                // changed by a concurrent writer.
+                ArtNodeRef::LeafSmall(_) => return Err(Restart),
+                _ => {
+                    let version = node.header().unwrap().lock.read_lock()?;
+                    match node.next_child(0, version)? {
+                        Some((_, child)) => node = child,
+                        None => return Err(Restart),
+                    }
+                }
             }
         }
-        false
     }
 
-    pub fn lock_version_or_restart(&self) -> bool {
-        if is_locked(&self.version) || is_obsolete(&self.version) {
-            return true;
+    pub fn prefix_matches(&self, key: &[u8], level: usize) -> Result<usize, usize> {
+        match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.prefix_matches(key, level),
+            ArtNodeRef::Inner16(ptr) => ptr.prefix_matches(key, level),
+            ArtNodeRef::Inner48(ptr) => ptr.prefix_matches(key, level),
+            ArtNodeRef::Inner256(ptr) => ptr.prefix_matches(key, level),
+            _ => Ok(level),
         }
-        let ver = self.version.load(Ordering::SeqCst);
-        match self.version.compare_exchange(
-            ver,
-            ver.wrapping_add(1),
-            Ordering::SeqCst,
-            Ordering::Relaxed,
-        ) {
-            Ok(_) => {
-                self.write_unlock();
-            }
-            Err(_) => return true,
+    }
+
+    /// Returns the child stored under `byte` in an inner node read at `version`.
+    ///
+    /// The version is validated before the child is looked at, see `RawChild`.
+    pub fn find_child(
+        &self,
+        byte: u8,
+        version: u64,
+    ) -> Result<Option<ArtNodeRef<'a, K, V>>, Restart> {
+        let child = match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.find_child(byte),
+            ArtNodeRef::Inner16(ptr) => ptr.find_child(byte),
+            ArtNodeRef::Inner48(ptr) => ptr.find_child(byte),
+            ArtNodeRef::Inner256(ptr) => ptr.find_child(byte),
+            _ => unreachable!("leaves have no children"),
+        };
+        self.header().unwrap().lock.check(version)?;
+        Ok(child.map(|child| unsafe { child.get() }))
+    }
+
+    /// Returns the child with the smallest key byte that is at least `from`, like
+    /// `find_child` does.
+    pub fn next_child(
+        &self,
+        from: usize,
+        version: u64,
+    ) -> Result<Option<KeyedChild<'a, K, V>>, Restart> {
+        let child = match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.next_child(from),
+            ArtNodeRef::Inner16(ptr) => ptr.next_child(from),
+            ArtNodeRef::Inner48(ptr) => ptr.next_child(from),
+            ArtNodeRef::Inner256(ptr) => ptr.next_child(from),
+            _ => unreachable!("leaves have no children"),
+        };
+        self.header().unwrap().lock.check(version)?;
+        Ok(child.map(|(byte, child)| (byte, unsafe { child.get() })))
+    }
+
+    /// Returns the child with the largest key byte that is below `before`, like
+    /// `find_child` does.
+    pub fn prev_child(
+        &self,
+        before: usize,
+        version: u64,
+    ) -> Result<Option<KeyedChild<'a, K, V>>, Restart> {
+        let child = match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.prev_child(before),
+            ArtNodeRef::Inner16(ptr) => ptr.prev_child(before),
+            ArtNodeRef::Inner48(ptr) => ptr.prev_child(before),
+            ArtNodeRef::Inner256(ptr) => ptr.prev_child(before),
+            _ => unreachable!("leaves have no children"),
+        };
+        self.header().unwrap().lock.check(version)?;
+        Ok(child.map(|(byte, child)| (byte, unsafe { child.get() })))
+    }
+
+    pub fn is_full(&self) -> bool {
+        match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.is_full(),
+            ArtNodeRef::Inner16(ptr) => ptr.is_full(),
+            ArtNodeRef::Inner48(ptr) => ptr.is_full(),
+            ArtNodeRef::Inner256(ptr) => ptr.is_full(),
+            _ => true,
         }
-        return false;
     }
 
-    pub fn read_unlock_or_restart(header: usize, version: usize) -> bool {
-        if header == version {
-            return false;
+    /// Requires the write lock of the node.
+    pub fn add_child(&self, node: ArtNode<K, V>, byte: u8) {
+        match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.add_child(node, byte),
+            ArtNodeRef::Inner16(ptr) => ptr.add_child(node, byte),
+            ArtNodeRef::Inner48(ptr) => ptr.add_child(node, byte),
+            ArtNodeRef::Inner256(ptr) => ptr.add_child(node, byte),
+            _ => unreachable!("leaves have no children"),
         }
-        return true;
     }
 
-    pub fn prefix_match<K: Digital>(&self, key: K, depth: usize) -> bool {
-        let k = key.to_le_bytes();
-        for i in 0..self.partial_len {
-            if *k.get(i + depth).unwrap() != self.partial[i] {
-                return false;
-            }
+    /// Requires the write lock of the node.
+    pub fn change(&self, byte: u8, node: ArtNode<K, V>) -> ArtNode<K, V> {
+        match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.change(byte, node),
+            ArtNodeRef::Inner16(ptr) => ptr.change(byte, node),
+            ArtNodeRef::Inner48(ptr) => ptr.change(byte, node),
+            ArtNodeRef::Inner256(ptr) => ptr.change(byte, node),
+            _ => unreachable!("leaves have no children"),
         }
-        return true;
     }
 
-    pub fn compute_prefix_match<K: ArtKey>(&self, key: &K, depth: usize) -> usize {
-        for i in 0..self.partial_len {
-            if key.bytes()[i + depth] != self.partial[i] {
-                return i;
-            }
+    pub fn is_underfull(&self) -> bool {
+        match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.is_underfull(),
+            ArtNodeRef::Inner16(ptr) => ptr.is_underfull(),
+            ArtNodeRef::Inner48(ptr) => ptr.is_underfull(),
+            ArtNodeRef::Inner256(ptr) => ptr.is_underfull(),
+            _ => false,
+        }
+    }
+
+    /// Requires the write lock of the node.
+    pub fn remove_child(&self, byte: u8) -> ArtNode<K, V> {
+        match *self {
+            ArtNodeRef::Inner4(ptr) => ptr.remove_child(byte),
+            ArtNodeRef::Inner16(ptr) => ptr.remove_child(byte),
+            ArtNodeRef::Inner48(ptr) => ptr.remove_child(byte),
+            ArtNodeRef::Inner256(ptr) => ptr.remove_child(byte),
+            _ => unreachable!("leaves have no children"),
+        }
+    }
+
+    /// Moves every child into a node of the next smaller type.
+    ///
+    /// Requires the write lock of the node, which becomes obsolete afterwards.
+    pub fn shrink(&self) -> ArtNode<K, V> {
+        match *self {
+            ArtNodeRef::Inner16(ptr) => ArtNode::Inner4(Owned::new(ptr.shrink())),
+            ArtNodeRef::Inner48(ptr) => ArtNode::Inner16(Owned::new(ptr.shrink())),
+            ArtNodeRef::Inner256(ptr) => ArtNode::Inner48(Owned::new(ptr.shrink())),
+            _ => unreachable!("only Node16, Node48 and Node256 can shrink"),
+        }
+    }
+
+    /// Moves every child into a node of the next larger type.
+    ///
+    /// Requires the write lock of the node, which becomes obsolete afterwards.
+    pub fn grow(&self) -> ArtNode<K, V> {
+        match *self {
+            ArtNodeRef::Inner4(ptr) => ArtNode::Inner16(Owned::new(ptr.grow())),
+            ArtNodeRef::Inner16(ptr) => ArtNode::Inner48(Owned::new(ptr.grow())),
+            ArtNodeRef::Inner48(ptr) => ArtNode::Inner256(Owned::new(ptr.grow())),
+            _ => unreachable!("only Node4, Node16 and Node48 can grow"),
+        }
+    }
+}
+
+/// A child slot that can be read while it is being replaced. It is written
+/// through the `Slots` of its node.
+pub struct AtomicArtNode<K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    data: AtomicPtr<u8>,
+    marker: PhantomData<ArtNode<K, V>>,
+}
+
+impl<K, V> AtomicArtNode<K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    pub fn empty() -> Self {
+        AtomicArtNode {
+            data: AtomicPtr::new(ptr::null_mut()),
+            marker: Default::default(),
+        }
+    }
+}
+
+/// The child slots of an inner node, with the bitmap marking the ones that hold
+/// a small leaf.
+///
+/// A small leaf fills its whole slot with its value, so the slot cannot say what
+/// it holds by itself. Nodes keep one bit per slot for that, next to the slots.
+pub struct Slots<'a, K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    children: &'a [AtomicArtNode<K, V>],
+    small: &'a [AtomicU8],
+}
+
+impl<'a, K, V> Slots<'a, K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    pub fn new(children: &'a [AtomicArtNode<K, V>], small: &'a [AtomicU8]) -> Self {
+        debug_assert!(small.len() * 8 >= children.len());
+        Slots { children, small }
+    }
+
+    fn is_small(&self, index: usize) -> bool {
+        self.small[index / 8].load(Ordering::Acquire) & (1 << (index % 8)) != 0
+    }
+
+    fn set_small(&self, index: usize, small: bool) {
+        let bit = 1 << (index % 8);
+        if small {
+            self.small[index / 8].fetch_or(bit, Ordering::Release);
+        } else {
+            self.small[index / 8].fetch_and(!bit, Ordering::Release);
+        }
+    }
+
+    /// Reads a slot without interpreting it.
+    #[inline]
+    pub fn load(&self, index: usize) -> RawChild<'a, K, V> {
+        RawChild {
+            data: self.children[index].data.load(Ordering::Acquire),
+            small: self.is_small(index),
+            marker: PhantomData,
+        }
+    }
+
+    /// Requires the write lock of the node.
+    pub fn is_empty(&self, index: usize) -> bool {
+        self.load(index).is_empty()
+    }
+
+    /// Stores `node` into an empty slot.
+    ///
+    /// Requires the write lock of the node.
+    pub fn store(&self, index: usize, node: ArtNode<K, V>) {
+        debug_assert!(self.is_empty(index), "overwriting a live child");
+        drop(self.swap(index, node));
+    }
+
+    /// Requires the write lock of the node.
+    pub fn swap(&self, index: usize, node: ArtNode<K, V>) -> ArtNode<K, V> {
+        let (data, small) = node.into_slot();
+        let was_small = self.is_small(index);
+        self.set_small(index, small);
+        let old = self.children[index].data.swap(data, Ordering::AcqRel);
+        unsafe { ArtNode::from_slot(old, was_small) }
+    }
+
+    /// Requires the write lock of the node.
+    pub fn take(&self, index: usize) -> ArtNode<K, V> {
+        self.swap(index, ArtNode::Empty)
+    }
+
+    /// Drops every child, for a node that is being dropped.
+    pub fn clear(&self) {
+        for index in 0..self.children.len() {
+            drop(self.take(index));
         }
-        self.partial_len
+    }
+}
+
+/// A child read from its slot without the lock of its node.
+///
+/// The word of the slot and its bit in the bitmap of the node are read one after
+/// the other, so they only belong together if no writer changed the node in
+/// between. A value read this way could look like any pointer, so the child is
+/// only looked at once the version of the node is validated.
+pub struct RawChild<'a, K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    data: *mut u8,
+    small: bool,
+    marker: PhantomData<ArtNodeRef<'a, K, V>>,
+}
+
+impl<'a, K, V> RawChild<'a, K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    pub fn is_empty(&self) -> bool {
+        self.data.is_null() && !self.small
     }
 
-    pub fn is_locked(&self) -> bool {
-        is_locked(&self.version)
+    /// Returns the child.
+    ///
+    /// The caller must hold the write lock of the node the child was read from, or
+    /// have validated the version of that node after the read.
+    pub unsafe fn get(self) -> ArtNodeRef<'a, K, V> {
+        ArtNodeRef::from_slot(self.data, self.small)
     }
+}
 
-    pub fn read_lock_or_restart(&self) -> Result<usize, ()> {
-        if is_locked(&self.version) {
-            return Err(());
+pub struct NodeHeader {
+    //NodeType: NodeType,
+    pub lock: VersionLock,
+    pub num_children: AtomicU16,
+    partial: [AtomicU8; MAX_PREFIX_LEN],
+    partial_len: AtomicUsize,
+}
+
+impl NodeHeader {
+    pub fn new() -> Self {
+        NodeHeader {
+            lock: VersionLock::new(),
+            num_children: AtomicU16::new(0),
+            partial_len: AtomicUsize::new(0),
+            partial: array::from_fn(|_| AtomicU8::new(0)),
         }
-        if is_obsolete(&self.version) {
-            return Err(());
+    }
+
+    pub fn get_partial_len(&self) -> usize {
+        self.partial_len.load(Ordering::Relaxed)
+    }
+
+    /// Returns the stored bytes of the compressed prefix, which are all of it
+    /// unless the prefix is longer than `MAX_PREFIX_LEN`.
+    pub fn get_partial(&self) -> ArrayVec<[u8; MAX_PREFIX_LEN]> {
+        let mut partial = ArrayVec::new();
+        for byte in self.partial.iter().take(self.get_partial_len()) {
+            partial.push(byte.load(Ordering::Relaxed));
         }
-        let ver = self.version.load(Ordering::SeqCst);
-        return Ok(ver);
+        partial
     }
 
-    fn write_unlock(&self) {
-        let ver = self.version.load(Ordering::SeqCst);
-        self.version.store(ver.wrapping_add(2), Ordering::SeqCst);
+    /// Returns `true` if the whole compressed prefix is stored in the node.
+    pub fn is_prefix_complete(&self) -> bool {
+        self.get_partial_len() <= MAX_PREFIX_LEN
     }
 
-    pub fn write_unlock_obsolete(&self) {
-        let ver = self.version.load(Ordering::SeqCst);
-        self.version.store(ver.wrapping_add(3), Ordering::SeqCst);
+    /// Sets the compressed prefix, of which only the first `MAX_PREFIX_LEN` bytes
+    /// are stored.
+    ///
+    /// Requires the write lock of the node.
+    pub fn set_partial(&self, partial: &[u8]) {
+        self.set_prefix(partial, partial.len());
     }
 
-    pub fn upgrade_to_write_lock_or_restart(&mut self, version: usize) -> Result<(), ()> {
-        if self
-            .version
-            .compare_and_swap(version, version + 2, Ordering::SeqCst)
-            != version + 2
-        {
-            return Err(());
+    /// Sets a compressed prefix of `len` bytes that starts with `stored`, which
+    /// must hold at least the bytes the node keeps.
+    ///
+    /// Requires the write lock of the node.
+    pub fn set_prefix(&self, stored: &[u8], len: usize) {
+        assert!(stored.len() >= len.min(MAX_PREFIX_LEN));
+        for (slot, byte) in self.partial.iter().zip(stored) {
+            slot.store(*byte, Ordering::Relaxed);
         }
-        return Ok(());
+        self.partial_len.store(len, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn num_children(&self) -> usize {
+        self.num_children.load(Ordering::Relaxed) as usize
     }
 
-    pub fn upgrade_to_write_lock_or_write_unlock_and_restart(
-        &mut self,
-        version: usize,
-        header: &mut NodeHeader,
-    ) -> Result<(), ()> {
-        if self
-            .version
-            .compare_and_swap(version, version + 2, Ordering::SeqCst)
-            == version + 2
-        {
-            header.write_unlock();
-            return Err(());
+    pub fn prefix_match(&self, key: &[u8], depth: usize) -> bool {
+        self.compute_prefix_match(key, depth) == self.get_partial_len()
+    }
+
+    /// Returns how many bytes of the compressed prefix match `key` at `depth`.
+    ///
+    /// Bytes past the stored ones are skipped optimistically and count as matching,
+    /// so a lookup must compare the whole key with the leaf it ends up at.
+    pub fn compute_prefix_match(&self, key: &[u8], depth: usize) -> usize {
+        let partial_len = self.get_partial_len();
+        for i in 0..partial_len.min(MAX_PREFIX_LEN) {
+            match key.get(i + depth) {
+                Some(byte) if *byte == self.partial[i].load(Ordering::Relaxed) => continue,
+                _ => return i,
+            }
         }
-        return Ok(());
+        partial_len
     }
 }
 
 #[test]
-fn lock() {
-    let mut header = NodeHeader::new();
-    assert_eq!(header.is_locked(), false);
-    assert_eq!(header.write_lock_or_restart(), false);
-    assert_eq!(header.is_locked(), true);
-    header.write_unlock();
-    assert_eq!(header.is_locked(), false);
+fn nodes_fit_their_cache_line_budget() {
+    const CACHE_LINE: usize = 64;
+    assert!(mem::size_of::<Node4<u64, u64>>() <= CACHE_LINE);
+    assert!(mem::size_of::<Node16<u64, u64>>() <= 3 * CACHE_LINE);
+    assert!(mem::size_of::<Node48<u64, u64>>() <= 11 * CACHE_LINE);
+    assert!(mem::size_of::<Node256<u64, u64>>() <= 33 * CACHE_LINE);
 }
 
 pub trait ArtNodeTrait<K, V>
@@ -217,20 +805,32 @@ where
 {
     fn new() -> Self;
 
-    fn add_child(&mut self, node: ArtNode<K, V>, byte: u8);
+    /// Requires the write lock of the node, a free slot and no child under `byte`.
+    fn add_child(&self, node: ArtNode<K, V>, byte: u8);
 
-    //fn clean_child(&mut self, byte: u8) -> bool;
+    /// Returns `true` if a child hangs under `byte`.
+    fn has_child(&self, byte: u8) -> bool {
+        self.find_child(byte).is_some()
+    }
 
-    fn prefix_matches(&self, key: K, level: usize) -> Result<usize, usize>;
+    //fn clean_child(&mut self, byte: u8) -> bool;
 
-    fn get_version(&self) -> usize;
+    fn prefix_matches(&self, key: &[u8], level: usize) -> Result<usize, usize>;
 
-    #[inline]
     fn is_full(&self) -> bool;
 
-    fn change(&mut self, key: u8, val: ArtNode<K, V>) -> bool;
+    /// Returns `true` if removing one more child should shrink the node.
+    fn is_underfull(&self) -> bool;
+
+    /// Unlinks the child stored under `byte` and hands it back.
+    ///
+    /// Requires the write lock of the node.
+    fn remove_child(&self, byte: u8) -> ArtNode<K, V>;
 
-    fn has_child(&self, byte: u8) -> bool;
+    /// Replaces the child stored under `key` and hands back the previous one.
+    ///
+    /// Requires the write lock of the node.
+    fn change(&self, key: u8, val: ArtNode<K, V>) -> ArtNode<K, V>;
 
     //fn grow_and_add(self, leaf: ArtNode<K, V>, byte: u8) -> ArtNode<K, V>;
 
@@ -242,19 +842,21 @@ where
     //#[inline]
     //fn header(&self) -> &NodeHeader;
 
-    #[inline]
-    fn find_child_mut(
-        &mut self,
-        byte: usize,
-        level: usize,
-        parent: ArtNode<K, V>,
-        version_parent: usize,
-    ) -> Result<&mut ArtNode<K, V>, ()>;
-
-    fn insert_and_unlock(&self, parent_node: Self, key: u8) -> (ArtNode<K, V>, bool);
-
-    //#[inline]
-    //fn find_child(&self, byte: u8) -> Option<&ArtNode<K, V>>;
+    /// Returns the child stored under `byte`, if any.
+    ///
+    /// The caller is expected to hold a read version of the node and to validate it
+    /// before the child is looked at.
+    fn find_child(&self, byte: u8) -> Option<RawChild<'_, K, V>>;
+
+    /// Returns the child with the smallest key byte that is at least `from`.
+    ///
+    /// Same validation rules as `find_child`.
+    fn next_child(&self, from: usize) -> Option<(u8, RawChild<'_, K, V>)>;
+
+    /// Returns the child with the largest key byte that is below `before`.
+    ///
+    /// Same validation rules as `find_child`.
+    fn prev_child(&self, before: usize) -> Option<(u8, RawChild<'_, K, V>)>;
 
     //#[inline]
     //fn has_child(&self, byte: u8) -> bool;
@@ -1,32 +1,52 @@
 use internal::Digital;
-use node;
-use node::ArtNode::Empty;
-use node::{ArtNode, ArtNodeTrait, NodeHeader};
+use node::{ArtNode, ArtNodeTrait, AtomicArtNode, NodeHeader, RawChild, Slots};
 use node4::Node4;
 use node48::Node48;
-use std::arch::x86_64::__m128i;
-use std::arch::x86_64::_mm_cmpeq_epi8;
-use std::arch::x86_64::_mm_loadu_si128;
-use std::arch::x86_64::_mm_movemask_epi8;
-use std::arch::x86_64::_mm_set1_epi8;
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+use std::arch::aarch64::{
+    vceqq_u8, vdupq_n_u8, vget_lane_u64, vld1q_u8, vreinterpret_u64_u8, vreinterpretq_u16_u8,
+    vshrn_n_u16,
+};
+#[cfg(target_arch = "x86")]
+use std::arch::x86::{
+    __m128i, _mm_broadcastb_epi8, _mm_cmpeq_epi8, _mm_cvtsi32_si128, _mm_loadu_si128,
+    _mm_movemask_epi8, _mm_set1_epi8,
+};
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{
+    __m128i, _mm_broadcastb_epi8, _mm_cmpeq_epi8, _mm_cvtsi32_si128, _mm_loadu_si128,
+    _mm_movemask_epi8, _mm_set1_epi8,
+};
+use std::array;
 use std::cmp::PartialEq;
-use std::intrinsics::cttz;
 use std::marker::PhantomData;
-use std::mem;
-use std::mem::ManuallyDrop;
 use std::sync::atomic::{AtomicU8, Ordering};
 
+// Aligned like `Leaf` to leave room for the child slot tag.
+#[repr(align(8))]
 pub struct Node16<K, T>
 where
     K: Default + PartialEq + Digital,
     T: 'static + Send + Sync,
 {
     pub header: NodeHeader,
-    pub keys: mem::ManuallyDrop<[u8; 16]>,
-    pub children: mem::ManuallyDrop<[ArtNode<K, T>; 16]>,
+    pub keys: [AtomicU8; 16],
+    pub children: [AtomicArtNode<K, T>; 16],
+    // One bit per child slot, set when the slot holds a small leaf.
+    pub small: [AtomicU8; 2],
     pub marker: PhantomData<K>,
 }
 
+impl<K, V> Drop for Node16<K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    fn drop(&mut self) {
+        self.slots().clear();
+    }
+}
+
 impl<K, V> ArtNodeTrait<K, V> for Node16<K, V>
 where
     K: Default + PartialEq + Digital,
@@ -35,112 +55,99 @@ where
     fn new() -> Self {
         Node16 {
             header: NodeHeader::new(),
-            keys: unsafe { mem::uninitialized() },
-            children: unsafe { mem::uninitialized() },
+            keys: array::from_fn(|_| AtomicU8::new(0)),
+            children: array::from_fn(|_| AtomicArtNode::empty()),
+            small: array::from_fn(|_| AtomicU8::new(0)),
             marker: Default::default(),
         }
     }
 
-    fn get_version(&self) -> usize {
-        self.header.read_version()
-    }
-
-    fn prefix_matches(&self, key: K, level: usize) -> Result<usize, usize> {
+    fn prefix_matches(&self, key: &[u8], level: usize) -> Result<usize, usize> {
         if self.header.prefix_match(key, level) {
             return Ok(level + self.header.get_partial_len());
         }
-        return Err(level);
+        Err(level)
     }
 
-    fn add_child(&mut self, node: ArtNode<K, V>, byte: u8) {}
+    fn add_child(&self, node: ArtNode<K, V>, byte: u8) {
+        debug_assert!(!self.has_child(byte), "adding a duplicate child");
+        // Keys are kept sorted, so shift the larger ones one slot up.
+        let count = self.header.num_children();
+        let mut pos = 0;
+        while pos < count && self.keys[pos].load(Ordering::Relaxed) < byte {
+            pos += 1;
+        }
+        for i in (pos..count).rev() {
+            self.keys[i + 1].store(self.keys[i].load(Ordering::Relaxed), Ordering::Relaxed);
+            self.slots().store(i + 1, self.slots().take(i));
+        }
+        self.keys[pos].store(byte, Ordering::Relaxed);
+        self.slots().store(pos, node);
+        self.header
+            .num_children
+            .store(count as u16 + 1, Ordering::Relaxed);
+    }
 
     fn is_full(&self) -> bool {
-        self.header.num_children >= 16
+        self.header.num_children() >= 16
     }
 
-    #[inline]
-    fn find_child_mut(
-        &mut self,
-        byte: usize,
-        level: usize,
-        parent: ArtNode<K, V>,
-        version_parent: usize,
-    ) -> Result<&mut ArtNode<K, V>, ()> {
-        let version = match self.header.read_lock_or_restart() {
-            Ok(ver) => ver,
-            Err(_) => return Err(()),
-        };
-        let key = if self.header.get_partial_len() == 0 {
-            byte.to_le_bytes()[level]
-        } else {
-            byte.to_le_bytes()[level + self.header.get_partial_len()]
-        };
-        let index = 0;
-
-        let raw_node_key = [
-            *self.keys.get(0).unwrap() as i8,
-            *self.keys.get(1).unwrap() as i8,
-            *self.keys.get(2).unwrap() as i8,
-            *self.keys.get(3).unwrap() as i8,
-            *self.keys.get(4).unwrap() as i8,
-            *self.keys.get(5).unwrap() as i8,
-            *self.keys.get(6).unwrap() as i8,
-            *self.keys.get(7).unwrap() as i8,
-            *self.keys.get(8).unwrap() as i8,
-            *self.keys.get(9).unwrap() as i8,
-            *self.keys.get(10).unwrap() as i8,
-            *self.keys.get(11).unwrap() as i8,
-            *self.keys.get(12).unwrap() as i8,
-            *self.keys.get(13).unwrap() as i8,
-            *self.keys.get(14).unwrap() as i8,
-            *self.keys.get(15).unwrap() as i8,
-        ];
-        let result: Option<u8>;
-        unsafe {
-            let node_key: __m128i = _mm_loadu_si128(raw_node_key.as_ptr() as *const _);
-            let key = _mm_set1_epi8(key as i8);
-            let cmp = _mm_cmpeq_epi8(key, node_key);
-            let mask = (1 << 16) - 1;
-            let index = _mm_movemask_epi8(cmp) & mask;
-            result = if index == 0 {
-                None
-            } else {
-                Some(index as u8 - 1)
-            };
+    fn is_underfull(&self) -> bool {
+        self.header.num_children() <= 3
+    }
+
+    fn remove_child(&self, byte: u8) -> ArtNode<K, V> {
+        let count = self.header.num_children();
+        let pos = (0..count)
+            .find(|&i| self.keys[i].load(Ordering::Relaxed) == byte)
+            .expect("removing a missing child");
+        let child = self.slots().take(pos);
+        for i in pos + 1..count {
+            self.keys[i - 1].store(self.keys[i].load(Ordering::Relaxed), Ordering::Relaxed);
+            self.slots().store(i - 1, self.slots().take(i));
         }
+        self.header
+            .num_children
+            .store(count as u16 - 1, Ordering::Relaxed);
+        child
+    }
 
-        match result {
-            Some(index) => {
-                let next_node = self.children.get_mut(index as usize);
-                match self.header.read_lock_or_restart() {
-                    Ok(ver) => if version == ver {
-                        match next_node {
-                            None => return Err(()),
-                            Some(mut nd) => return Ok(nd),
-                        }
-                    } else {
-                        return Err(());
-                    },
-                    Err(()) => return Err(()),
-                }
-            }
-            None => return Err(()),
+    #[inline]
+    fn find_child(&self, byte: u8) -> Option<RawChild<'_, K, V>> {
+        let mut keys = [0u8; 16];
+        for (raw, key) in keys.iter_mut().zip(self.keys.iter()) {
+            *raw = key.load(Ordering::Relaxed);
         }
+        find_key(&keys, self.header.num_children(), byte).map(|index| self.slots().load(index))
     }
 
-    fn insert_and_unlock(&self, parent_node: Self, key: u8) -> (ArtNode<K, V>, bool) {
-        return (Empty, false);
+    fn next_child(&self, from: usize) -> Option<(u8, RawChild<'_, K, V>)> {
+        for index in 0..self.header.num_children() {
+            let byte = self.keys[index].load(Ordering::Relaxed);
+            if byte as usize >= from {
+                return Some((byte, self.slots().load(index)));
+            }
+        }
+        None
     }
 
-    fn change(&mut self, key: u8, val: ArtNode<K, V>) -> bool {
-        return false;
+    fn prev_child(&self, before: usize) -> Option<(u8, RawChild<'_, K, V>)> {
+        for index in (0..self.header.num_children()).rev() {
+            let byte = self.keys[index].load(Ordering::Relaxed);
+            if (byte as usize) < before {
+                return Some((byte, self.slots().load(index)));
+            }
+        }
+        None
     }
 
-    fn has_child(&self, byte: u8) -> bool {
-        match self.children[byte as usize] {
-            ArtNode::Empty => false,
-            _ => true,
+    fn change(&self, key: u8, val: ArtNode<K, V>) -> ArtNode<K, V> {
+        for index in 0..self.header.num_children() {
+            if self.keys[index].load(Ordering::Relaxed) == key {
+                return self.slots().swap(index, val);
+            }
         }
+        unreachable!("changing a missing child")
     }
 }
 
@@ -149,62 +156,183 @@ where
     K: Default + PartialEq + Digital,
     V: 'static + Send + Sync,
 {
-    fn grow(&mut self) -> Node48<K, V> {
-        let mut keys = unsafe { make_array!(256, AtomicU8::new(0)) };
-        let mut children = unsafe { ManuallyDrop::new(make_array!(48, ArtNode::Empty)) };
-        let mut new_children_index = 0;
-        for index in 0..self.header.num_children - 1 {
-            let new_index = self.keys.get(index as usize).unwrap();
-            let mut _key = keys.get_mut(*new_index as usize).unwrap();
-            _key.store(new_children_index, Ordering::Relaxed);
-
-            let mut _c = children.get_mut(new_children_index as usize).unwrap();
-            _c = self.children.get_mut(index as usize).unwrap();
-
-            new_children_index += 1
+    pub fn slots(&self) -> Slots<'_, K, V> {
+        Slots::new(&self.children, &self.small)
+    }
+
+    pub fn grow(&self) -> Node48<K, V> {
+        let n = Node48::new();
+        n.header
+            .set_prefix(&self.header.get_partial(), self.header.get_partial_len());
+        let count = self.header.num_children();
+        for index in 0..count {
+            let key = self.keys[index].load(Ordering::Relaxed);
+            n.keys[key as usize].store(index as u8 + 1, Ordering::Relaxed);
+            n.slots().store(index, self.slots().take(index));
         }
-        return Node48 {
-            header: self.header.clone(),
-            keys: keys,
-            children: children,
-            marker: Default::default(),
-        };
-    }
-
-    fn shrink(&mut self) -> Node4<K, V> {
-        let mut keys: [AtomicU8; 256] = unsafe { mem::uninitialized() };
-        let mut children: mem::ManuallyDrop<[ArtNode<K, V>; 48]> = unsafe { mem::uninitialized() };
-        let mut new_children_index = 0;
-        for index in 0..15 {
-            match self.children.get(index as usize).unwrap() {
-                ArtNode::Empty => continue,
-                _ => {
-                    let mut _k = keys.get_mut(index).unwrap();
-                    _k.store(new_children_index as u8, Ordering::Relaxed);
-
-                    let mut _c = children.get_mut(new_children_index as usize).unwrap();
-                    _c = self.children.get_mut(index as usize).unwrap();
-                    new_children_index += new_children_index;
-                }
-            }
+        n.header.num_children.store(count as u16, Ordering::Relaxed);
+        n
+    }
+
+    pub fn shrink(&self) -> Node4<K, V> {
+        let n = Node4::new();
+        n.header
+            .set_prefix(&self.header.get_partial(), self.header.get_partial_len());
+        let count = self.header.num_children();
+        for index in 0..count {
+            n.keys[index].store(self.keys[index].load(Ordering::Relaxed), Ordering::Relaxed);
+            n.slots().store(index, self.slots().take(index));
         }
-        return Node4 {
-            header: self.header.clone(),
-            keys: keys,
-            children: children,
-            marker: Default::default(),
-        };
+        n.header.num_children.store(count as u16, Ordering::Relaxed);
+        n
     }
 }
 
-impl<K, V> Drop for Node16<K, V>
-where
-    K: Default + PartialEq + Digital,
-    V: 'static + Send + Sync,
-{
-    fn drop(&mut self) {
-        for i in 0..self.header.num_children {
-            drop(&mut self.children[i as usize]);
+/// Returns the position of the first `byte` among the first `len` of `keys`.
+///
+/// The other slots may hold stale keys and never match. On x86 the vector path
+/// is picked at run time from what the CPU supports, AVX2 before SSE2, and the
+/// choice is cached after the first search. NEON is part of every aarch64 target,
+/// so that path is picked at compile time.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+fn find_key(keys: &[u8; 16], len: usize, byte: u8) -> Option<usize> {
+    match x86_search() {
+        SEARCH_AVX2 => unsafe { find_key_avx2(keys, len, byte) },
+        SEARCH_SSE2 => unsafe { find_key_sse2(keys, len, byte) },
+        _ => find_key_scalar(keys, len, byte),
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+#[inline]
+fn find_key(keys: &[u8; 16], len: usize, byte: u8) -> Option<usize> {
+    find_key_neon(keys, len, byte)
+}
+
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    all(target_arch = "aarch64", target_feature = "neon")
+)))]
+#[inline]
+fn find_key(keys: &[u8; 16], len: usize, byte: u8) -> Option<usize> {
+    find_key_scalar(keys, len, byte)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const SEARCH_UNDETECTED: u8 = 0;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const SEARCH_SCALAR: u8 = 1;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const SEARCH_SSE2: u8 = 2;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const SEARCH_AVX2: u8 = 3;
+
+// The search `find_key` settled on, one of the `SEARCH_*` constants.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+static SEARCH: AtomicU8 = AtomicU8::new(SEARCH_UNDETECTED);
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+fn x86_search() -> u8 {
+    let search = SEARCH.load(Ordering::Relaxed);
+    if search != SEARCH_UNDETECTED {
+        return search;
+    }
+    // Racing threads detect the same features, so whichever store lands is right.
+    let search = if is_x86_feature_detected!("avx2") {
+        SEARCH_AVX2
+    } else if is_x86_feature_detected!("sse2") {
+        SEARCH_SSE2
+    } else {
+        SEARCH_SCALAR
+    };
+    SEARCH.store(search, Ordering::Relaxed);
+    search
+}
+
+#[cfg(any(test, not(all(target_arch = "aarch64", target_feature = "neon"))))]
+fn find_key_scalar(keys: &[u8; 16], len: usize, byte: u8) -> Option<usize> {
+    keys[..len].iter().position(|key| *key == byte)
+}
+
+/// Returns the first live slot set in `mask`, which has one bit per slot.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+fn first_live(mask: i32, len: usize) -> Option<usize> {
+    let mask = mask as u32 & ((1 << len) - 1);
+    if mask == 0 {
+        return None;
+    }
+    Some(mask.trailing_zeros() as usize)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn find_key_sse2(keys: &[u8; 16], len: usize, byte: u8) -> Option<usize> {
+    let keys = _mm_loadu_si128(keys.as_ptr() as *const __m128i);
+    first_live(
+        _mm_movemask_epi8(_mm_cmpeq_epi8(_mm_set1_epi8(byte as i8), keys)),
+        len,
+    )
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn find_key_avx2(keys: &[u8; 16], len: usize, byte: u8) -> Option<usize> {
+    let keys = _mm_loadu_si128(keys.as_ptr() as *const __m128i);
+    let byte = _mm_broadcastb_epi8(_mm_cvtsi32_si128(byte as i32));
+    first_live(_mm_movemask_epi8(_mm_cmpeq_epi8(byte, keys)), len)
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+fn find_key_neon(keys: &[u8; 16], len: usize, byte: u8) -> Option<usize> {
+    let mask = unsafe {
+        let matches = vceqq_u8(vld1q_u8(keys.as_ptr()), vdupq_n_u8(byte));
+        // NEON has no movemask: shifting every 16-bit lane right by 4 and narrowing
+        // it to 8 bits leaves a nibble per slot.
+        let nibbles = vshrn_n_u16::<4>(vreinterpretq_u16_u8(matches));
+        vget_lane_u64::<0>(vreinterpret_u64_u8(nibbles))
+    };
+    let live = if len == 16 {
+        !0
+    } else {
+        (1u64 << (4 * len)) - 1
+    };
+    let mask = mask & live;
+    if mask == 0 {
+        return None;
+    }
+    Some(mask.trailing_zeros() as usize / 4)
+}
+
+#[test]
+fn find_key_paths_agree() {
+    let mut spread = [0u8; 16];
+    let mut repeated = [0u8; 16];
+    for i in 0..16 {
+        spread[i] = i as u8 * 17;
+        repeated[i] = i as u8 % 5;
+    }
+    for keys in &[spread, repeated, [0; 16], [255; 16]] {
+        for len in 0..17 {
+            for byte in 0..=255 {
+                let expected = (0..len).find(|&i| keys[i] == byte);
+                assert_eq!(find_key_scalar(keys, len, byte), expected);
+                assert_eq!(find_key(keys, len, byte), expected);
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                {
+                    if is_x86_feature_detected!("sse2") {
+                        assert_eq!(unsafe { find_key_sse2(keys, len, byte) }, expected);
+                    }
+                    if is_x86_feature_detected!("avx2") {
+                        assert_eq!(unsafe { find_key_avx2(keys, len, byte) }, expected);
+                    }
+                }
+                #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+                assert_eq!(find_key_neon(keys, len, byte), expected);
+            }
         }
     }
 }
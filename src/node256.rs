@@ -1,23 +1,35 @@
 use internal::Digital;
-use node;
-use node::ArtNode::Empty;
-use node::{ArtNode, ArtNodeTrait, NodeHeader};
+use node::{ArtNode, ArtNodeTrait, AtomicArtNode, NodeHeader, RawChild, Slots};
 use node48::Node48;
+use std::array;
 use std::cmp::PartialEq;
 use std::marker::PhantomData;
-use std::mem::ManuallyDrop;
 use std::sync::atomic::{AtomicU8, Ordering};
 
+// Aligned like `Leaf` to leave room for the child slot tag.
+#[repr(align(8))]
 pub struct Node256<K, T>
 where
     K: Default + PartialEq + Digital,
     T: 'static + Send + Sync,
 {
     pub header: NodeHeader,
-    pub children: [ArtNode<K, T>; 256],
+    pub children: [AtomicArtNode<K, T>; 256],
+    // One bit per child slot, set when the slot holds a small leaf.
+    pub small: [AtomicU8; 32],
     pub marker: PhantomData<T>,
 }
 
+impl<K, V> Drop for Node256<K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    fn drop(&mut self) {
+        self.slots().clear();
+    }
+}
+
 impl<K, V> ArtNodeTrait<K, V> for Node256<K, V>
 where
     K: Default + PartialEq + Digital,
@@ -26,115 +38,93 @@ where
     fn new() -> Self {
         Node256 {
             header: NodeHeader::new(),
-            children: unsafe { make_array!(256, ArtNode::Empty) },
+            children: array::from_fn(|_| AtomicArtNode::empty()),
+            small: array::from_fn(|_| AtomicU8::new(0)),
             marker: Default::default(),
         }
     }
 
-    fn prefix_matches(&self, key: K, level: usize) -> Result<usize, usize> {
+    fn prefix_matches(&self, key: &[u8], level: usize) -> Result<usize, usize> {
         if self.header.prefix_match(key, level) {
             return Ok(level + self.header.get_partial_len());
         }
-        return Err(level);
+        Err(level)
     }
 
-    fn get_version(&self) -> usize {
-        self.header.read_version()
+    fn add_child(&self, node: ArtNode<K, V>, byte: u8) {
+        self.slots().store(byte as usize, node);
+        self.header.num_children.fetch_add(1, Ordering::Relaxed);
     }
 
-    fn add_child(&mut self, node: ArtNode<K, V>, byte: u8) {}
-
     fn is_full(&self) -> bool {
-        self.header.num_children >= 256
+        self.header.num_children() >= 256
     }
 
-    #[inline]
-    fn find_child_mut(
-        &mut self,
-        byte: usize,
-        level: usize,
-        parent: ArtNode<K, V>,
-        version_parent: usize,
-    ) -> Result<&mut ArtNode<K, V>, ()> {
-        let version = match self.header.read_lock_or_restart() {
-            Ok(ver) => ver,
-            Err(_) => return Err(()),
-        };
-        let index = if self.header.get_partial_len() == 0 {
-            byte.to_le_bytes()[level]
-        } else {
-            byte.to_le_bytes()[level + self.header.get_partial_len()]
-        };
-
-        let next_node = self.children.get_mut(index as usize);
-        match self.header.read_lock_or_restart() {
-            Ok(ver) => if version == ver {
-                match next_node {
-                    None => return Err(()),
-                    Some(mut nd) => return Ok(nd),
-                }
-            } else {
-                return Err(());
-            },
-            Err(_) => return Err(()),
-        }
+    fn is_underfull(&self) -> bool {
+        self.header.num_children() <= 37
     }
 
-    fn insert_and_unlock(&self, parent_node: Self, key: u8) -> (ArtNode<K, V>, bool) {
-        return (Empty, false);
+    fn remove_child(&self, byte: u8) -> ArtNode<K, V> {
+        self.header.num_children.fetch_sub(1, Ordering::Relaxed);
+        self.slots().take(byte as usize)
     }
 
-    fn change(&mut self, key: u8, val: ArtNode<K, V>) -> bool {
-        return false;
+    #[inline]
+    fn find_child(&self, byte: u8) -> Option<RawChild<'_, K, V>> {
+        let child = self.slots().load(byte as usize);
+        if child.is_empty() {
+            return None;
+        }
+        Some(child)
     }
 
-    fn has_child(&self, byte: u8) -> bool {
-        match self.children[byte as usize] {
-            ArtNode::Empty => false,
-            _ => true,
+    fn next_child(&self, from: usize) -> Option<(u8, RawChild<'_, K, V>)> {
+        for byte in from..256 {
+            let child = self.slots().load(byte);
+            if !child.is_empty() {
+                return Some((byte as u8, child));
+            }
         }
+        None
     }
-}
 
-impl<K, V> Node256<K, V>
-where
-    K: Default + PartialEq + Digital,
-    V: 'static + Send + Sync,
-{
-    fn shrink(&mut self) -> Node48<K, V> {
-        let mut keys = unsafe { make_array!(256, AtomicU8::new(0)) };
-        let mut children = unsafe { ManuallyDrop::new(make_array!(48, ArtNode::Empty)) };
-        let mut new_children_index = 0;
-        for index in 0..255 {
-            match self.children.get(index as usize).unwrap() {
-                ArtNode::Empty => continue,
-                _ => {
-                    let mut _k = keys.get_mut(index).unwrap();
-                    _k.store(new_children_index as u8, Ordering::Relaxed);
-
-                    let mut _c = children.get_mut(new_children_index as usize).unwrap();
-                    _c = self.children.get_mut(index as usize).unwrap();
-                    new_children_index += new_children_index;
-                }
+    fn prev_child(&self, before: usize) -> Option<(u8, RawChild<'_, K, V>)> {
+        for byte in (0..before).rev() {
+            let child = self.slots().load(byte);
+            if !child.is_empty() {
+                return Some((byte as u8, child));
             }
         }
-        return Node48 {
-            header: NodeHeader::new(),
-            keys: keys,
-            children: children,
-            marker: Default::default(),
-        };
+        None
+    }
+
+    fn change(&self, key: u8, val: ArtNode<K, V>) -> ArtNode<K, V> {
+        self.slots().swap(key as usize, val)
     }
 }
 
-impl<K, V> Drop for Node256<K, V>
+impl<K, V> Node256<K, V>
 where
     K: Default + PartialEq + Digital,
     V: 'static + Send + Sync,
 {
-    fn drop(&mut self) {
-        for i in 0..256 {
-            drop(&mut self.children[i as usize]);
+    pub fn slots(&self) -> Slots<'_, K, V> {
+        Slots::new(&self.children, &self.small)
+    }
+
+    pub fn shrink(&self) -> Node48<K, V> {
+        let n = Node48::new();
+        n.header
+            .set_prefix(&self.header.get_partial(), self.header.get_partial_len());
+        let mut count = 0;
+        for byte in 0..256 {
+            if !self.slots().is_empty(byte) {
+                n.keys[byte].store(count as u8 + 1, Ordering::Relaxed);
+                n.slots().store(count, self.slots().take(byte));
+                count += 1;
+            }
         }
+        n.header.num_children.store(count as u16, Ordering::Relaxed);
+        n
     }
 }
@@ -1,24 +1,34 @@
 use internal::Digital;
-use node;
-use node::ArtNode::Empty;
-use node::{ArtNode, ArtNodeTrait, NodeHeader};
+use node::{ArtNode, ArtNodeRef, ArtNodeTrait, AtomicArtNode, NodeHeader, RawChild, Slots};
 use node16::Node16;
-use node48::Node48;
+use std::array;
 use std::cmp::PartialEq;
 use std::marker::PhantomData;
-use std::mem::ManuallyDrop;
 use std::sync::atomic::{AtomicU8, Ordering};
-use std::{mem, ptr};
 
+// Aligned like `Leaf` to leave room for the child slot tag.
+#[repr(align(8))]
 pub struct Node4<K, T>
 where
     K: Default + PartialEq + Digital,
     T: 'static + Send + Sync,
 {
     pub header: NodeHeader,
-    pub keys: [AtomicU8; 256],
-    pub children: mem::ManuallyDrop<[ArtNode<K, T>; 48]>,
-    pub marker: PhantomData<ArtNode<K, T>>,
+    pub keys: [AtomicU8; 4],
+    pub children: [AtomicArtNode<K, T>; 4],
+    // One bit per child slot, set when the slot holds a small leaf.
+    pub small: [AtomicU8; 1],
+    pub marker: PhantomData<K>,
+}
+
+impl<K, V> Drop for Node4<K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    fn drop(&mut self) {
+        self.slots().clear();
+    }
 }
 
 impl<K, V> ArtNodeTrait<K, V> for Node4<K, V>
@@ -29,95 +39,102 @@ where
     fn new() -> Self {
         Node4 {
             header: NodeHeader::new(),
-            keys: unsafe { mem::uninitialized() },
-            children: unsafe { mem::uninitialized() },
+            keys: array::from_fn(|_| AtomicU8::new(0)),
+            children: array::from_fn(|_| AtomicArtNode::empty()),
+            small: array::from_fn(|_| AtomicU8::new(0)),
             marker: Default::default(),
         }
     }
 
-    fn get_version(&self) -> usize {
-        self.header.read_version()
-    }
-
-    fn prefix_matches(&self, key: K, level: usize) -> Result<usize, usize> {
+    fn prefix_matches(&self, key: &[u8], level: usize) -> Result<usize, usize> {
         if self.header.prefix_match(key, level) {
             return Ok(level + self.header.get_partial_len());
         }
-        return Err(level);
+        Err(level)
     }
 
-    fn add_child(&mut self, node: ArtNode<K, V>, byte: u8) {
-        self.header.write_unlock_obsolete();
+    fn add_child(&self, node: ArtNode<K, V>, byte: u8) {
+        debug_assert!(!self.has_child(byte), "adding a duplicate child");
+        // Keys are kept sorted, so shift the larger ones one slot up.
+        let count = self.header.num_children();
+        let mut pos = 0;
+        while pos < count && self.keys[pos].load(Ordering::Relaxed) < byte {
+            pos += 1;
+        }
+        for i in (pos..count).rev() {
+            self.keys[i + 1].store(self.keys[i].load(Ordering::Relaxed), Ordering::Relaxed);
+            self.slots().store(i + 1, self.slots().take(i));
+        }
+        self.keys[pos].store(byte, Ordering::Relaxed);
+        self.slots().store(pos, node);
+        self.header
+            .num_children
+            .store(count as u16 + 1, Ordering::Relaxed);
     }
 
     fn is_full(&self) -> bool {
-        self.header.num_children >= 4
+        self.header.num_children() >= 4
     }
 
-    #[inline]
-    fn find_child_mut(
-        &mut self,
-        byte: usize,
-        level: usize,
-        parent: ArtNode<K, V>,
-        version_parent: usize,
-    ) -> Result<&mut ArtNode<K, V>, ()> {
-        let version = match self.header.read_lock_or_restart() {
-            Ok(ver) => ver,
-            Err(_) => return Err(()),
-        };
-
-        let key = if self.header.get_partial_len() == 0 {
-            byte.to_le_bytes()[level]
-        } else {
-            byte.to_le_bytes()[level + self.header.get_partial_len()]
-        };
+    fn is_underfull(&self) -> bool {
+        // Node4 is the smallest node, a Node4 with a single child is merged into it
+        // instead.
+        false
+    }
 
-        let mut index = 0;
-        let mut result: Option<u8> = None;
-        for rkey in self.keys.iter() {
-            if index + 1 <= self.header.num_children {
-                result = None;
-                break;
-            }
-            if rkey.load(Ordering::Relaxed) == key {
-                result = Some(key);
-                break;
-            }
-            index += 1;
+    fn remove_child(&self, byte: u8) -> ArtNode<K, V> {
+        let count = self.header.num_children();
+        let pos = (0..count)
+            .find(|&i| self.keys[i].load(Ordering::Relaxed) == byte)
+            .expect("removing a missing child");
+        let child = self.slots().take(pos);
+        for i in pos + 1..count {
+            self.keys[i - 1].store(self.keys[i].load(Ordering::Relaxed), Ordering::Relaxed);
+            self.slots().store(i - 1, self.slots().take(i));
         }
-        match result {
-            Some(index) => {
-                let next_node = self.children.get_mut(index as usize);
-                match self.header.read_lock_or_restart() {
-                    Ok(ver) => if version == ver {
-                        match next_node {
-                            None => return Err(()),
-                            Some(mut nd) => return Ok(nd),
-                        }
-                    } else {
-                        return Err(());
-                    },
-                    Err(_) => return Err(()),
-                }
+        self.header
+            .num_children
+            .store(count as u16 - 1, Ordering::Relaxed);
+        child
+    }
+
+    #[inline]
+    fn find_child(&self, byte: u8) -> Option<RawChild<'_, K, V>> {
+        for index in 0..self.header.num_children() {
+            if self.keys[index].load(Ordering::Relaxed) == byte {
+                return Some(self.slots().load(index));
             }
-            None => return Err(()),
         }
+        None
     }
 
-    fn insert_and_unlock(&self, parent_node: Self, key: u8) -> (ArtNode<K, V>, bool) {
-        return (Empty, false);
+    fn next_child(&self, from: usize) -> Option<(u8, RawChild<'_, K, V>)> {
+        for index in 0..self.header.num_children() {
+            let byte = self.keys[index].load(Ordering::Relaxed);
+            if byte as usize >= from {
+                return Some((byte, self.slots().load(index)));
+            }
+        }
+        None
     }
 
-    fn change(&mut self, key: u8, val: ArtNode<K, V>) -> bool {
-        return false;
+    fn prev_child(&self, before: usize) -> Option<(u8, RawChild<'_, K, V>)> {
+        for index in (0..self.header.num_children()).rev() {
+            let byte = self.keys[index].load(Ordering::Relaxed);
+            if (byte as usize) < before {
+                return Some((byte, self.slots().load(index)));
+            }
+        }
+        None
     }
 
-    fn has_child(&self, byte: u8) -> bool {
-        match self.children[byte as usize] {
-            ArtNode::Empty => false,
-            _ => true,
+    fn change(&self, key: u8, val: ArtNode<K, V>) -> ArtNode<K, V> {
+        for index in 0..self.header.num_children() {
+            if self.keys[index].load(Ordering::Relaxed) == key {
+                return self.slots().swap(index, val);
+            }
         }
+        unreachable!("changing a missing child")
     }
 }
 
@@ -126,41 +143,29 @@ where
     K: Default + PartialEq + Digital,
     V: 'static + Send + Sync,
 {
-    fn grow(&mut self) -> Node16<K, V> {
-        let mut keys: mem::ManuallyDrop<[u8; 16]> = unsafe { mem::uninitialized() };
-        let mut children: mem::ManuallyDrop<[ArtNode<K, V>; 16]> = unsafe { mem::uninitialized() };
-        let mut old: Vec<u8> = Vec::with_capacity(self.header.num_children as usize);
-        for index in 0..self.header.num_children {
-            old.push(
-                self.keys
-                    .get(index as usize)
-                    .unwrap()
-                    .load(Ordering::Relaxed)
-                    .clone(),
-            );
-        }
-        unsafe {
-            ptr::copy_nonoverlapping(old.as_mut_ptr(), keys.as_mut_ptr(), 4);
-            ptr::copy_nonoverlapping(self.children.as_mut_ptr(), children.as_mut_ptr(), 4);
-        }
-        let n = Node16 {
-            header: self.header.clone(),
-            keys: keys,
-            children: children,
-            marker: Default::default(),
-        };
-        n
+    pub fn slots(&self) -> Slots<'_, K, V> {
+        Slots::new(&self.children, &self.small)
     }
-}
 
-impl<K, V> Drop for Node4<K, V>
-where
-    K: Default + PartialEq + Digital,
-    V: 'static + Send + Sync,
-{
-    fn drop(&mut self) {
-        for i in 0..self.header.num_children {
-            drop(&mut self.children[i as usize]);
+    /// Returns the key byte and the child of the first occupied slot.
+    ///
+    /// Requires the write lock of the node.
+    pub fn first_child(&self) -> (u8, ArtNodeRef<'_, K, V>) {
+        (self.keys[0].load(Ordering::Relaxed), unsafe {
+            self.slots().load(0).get()
+        })
+    }
+
+    pub fn grow(&self) -> Node16<K, V> {
+        let n = Node16::new();
+        n.header
+            .set_prefix(&self.header.get_partial(), self.header.get_partial_len());
+        let count = self.header.num_children();
+        for index in 0..count {
+            n.keys[index].store(self.keys[index].load(Ordering::Relaxed), Ordering::Relaxed);
+            n.slots().store(index, self.slots().take(index));
         }
+        n.header.num_children.store(count as u16, Ordering::Relaxed);
+        n
     }
 }
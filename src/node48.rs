@@ -1,26 +1,38 @@
 use internal::Digital;
-use node;
-use node::ArtNode::Empty;
-use node::{ArtNode, ArtNodeTrait, NodeHeader};
+use node::{ArtNode, ArtNodeTrait, AtomicArtNode, NodeHeader, RawChild, Slots};
 use node16::Node16;
 use node256::Node256;
+use std::array;
 use std::cmp::PartialEq;
 use std::marker::PhantomData;
-use std::mem::ManuallyDrop;
 use std::sync::atomic::{AtomicU8, Ordering};
-use std::{mem, ptr};
 
+// Aligned like `Leaf` to leave room for the child slot tag.
+#[repr(align(8))]
 pub struct Node48<K, T>
 where
     K: Default + PartialEq + Digital,
     T: 'static + Send + Sync,
 {
     pub header: NodeHeader,
+    /// Child index plus one for every key byte, zero marks an absent child.
     pub keys: [AtomicU8; 256],
-    pub children: mem::ManuallyDrop<[ArtNode<K, T>; 48]>,
+    pub children: [AtomicArtNode<K, T>; 48],
+    // One bit per child slot, set when the slot holds a small leaf.
+    pub small: [AtomicU8; 6],
     pub marker: PhantomData<T>,
 }
 
+impl<K, V> Drop for Node48<K, V>
+where
+    K: Default + PartialEq + Digital,
+    V: 'static + Send + Sync,
+{
+    fn drop(&mut self) {
+        self.slots().clear();
+    }
+}
+
 impl<K, V> ArtNodeTrait<K, V> for Node48<K, V>
 where
     K: Default + PartialEq + Digital,
@@ -29,83 +41,83 @@ where
     fn new() -> Self {
         Node48 {
             header: NodeHeader::new(),
-            keys: unsafe { make_array!(256, AtomicU8::new(0)) },
-            children: unsafe { ManuallyDrop::new(make_array!(48, ArtNode::Empty)) },
+            keys: array::from_fn(|_| AtomicU8::new(0)),
+            children: array::from_fn(|_| AtomicArtNode::empty()),
+            small: array::from_fn(|_| AtomicU8::new(0)),
             marker: Default::default(),
         }
     }
 
-    fn get_version(&self) -> usize {
-        self.header.read_version()
-    }
-
-    fn prefix_matches(&self, key: K, level: usize) -> Result<usize, usize> {
+    fn prefix_matches(&self, key: &[u8], level: usize) -> Result<usize, usize> {
         if self.header.prefix_match(key, level) {
             return Ok(level + self.header.get_partial_len());
         }
-        return Err(level);
+        Err(level)
     }
 
-    fn add_child(&mut self, node: ArtNode<K, V>, byte: u8) {}
+    fn add_child(&self, node: ArtNode<K, V>, byte: u8) {
+        debug_assert!(!self.has_child(byte), "adding a duplicate child");
+        let slots = self.slots();
+        let index = (0..self.children.len())
+            .position(|index| slots.is_empty(index))
+            .expect("adding a child to a full node");
+        slots.store(index, node);
+        self.keys[byte as usize].store(index as u8 + 1, Ordering::Release);
+        self.header.num_children.fetch_add(1, Ordering::Relaxed);
+    }
 
     fn is_full(&self) -> bool {
-        self.header.num_children >= 48
+        self.header.num_children() >= 48
+    }
+
+    fn is_underfull(&self) -> bool {
+        self.header.num_children() <= 12
+    }
+
+    fn remove_child(&self, byte: u8) -> ArtNode<K, V> {
+        let index = self.keys[byte as usize].swap(0, Ordering::Release);
+        debug_assert!(index != 0, "removing a missing child");
+        self.header.num_children.fetch_sub(1, Ordering::Relaxed);
+        self.slots().take(index as usize - 1)
     }
 
     #[inline]
-    fn find_child_mut(
-        &mut self,
-        byte: usize,
-        level: usize,
-        parent: ArtNode<K, V>,
-        version_parent: usize,
-    ) -> Result<&mut ArtNode<K, V>, ()> {
-        let version = match self.header.read_lock_or_restart() {
-            Ok(ver) => ver,
-            Err(_) => return Err(()),
-        };
-        let key = if self.header.get_partial_len() == 0 {
-            byte.to_le_bytes()[level]
-        } else {
-            byte.to_le_bytes()[level + self.header.get_partial_len()]
-        };
-        let result = self.keys.get(key as usize);
-        match result {
-            Some(index) => {
-                let id = index.load(Ordering::Relaxed);
-                if id == 0 {
-                    return Err(());
-                }
-                let next_node = self.children.get_mut(id as usize);
-                match self.header.read_lock_or_restart() {
-                    Ok(ver) => if version == ver {
-                        match next_node {
-                            None => return Err(()),
-                            Some(mut nd) => return Ok(nd),
-                        }
-                    } else {
-                        return Err(());
-                    },
-                    Err(_) => return Err(()),
-                }
-            }
-            None => return Err(()),
+    fn find_child(&self, byte: u8) -> Option<RawChild<'_, K, V>> {
+        let index = self.keys[byte as usize].load(Ordering::Acquire);
+        if index == 0 {
+            return None;
         }
+        // A racing writer may leave any index behind, the caller finds out later.
+        if index as usize > self.children.len() {
+            return None;
+        }
+        Some(self.slots().load(index as usize - 1))
     }
 
-    fn insert_and_unlock(&self, parent_node: Self, key: u8) -> (ArtNode<K, V>, bool) {
-        return (Empty, false);
+    fn next_child(&self, from: usize) -> Option<(u8, RawChild<'_, K, V>)> {
+        for byte in from..256 {
+            let index = self.keys[byte].load(Ordering::Acquire);
+            if index != 0 && index as usize <= self.children.len() {
+                return Some((byte as u8, self.slots().load(index as usize - 1)));
+            }
+        }
+        None
     }
 
-    fn change(&mut self, key: u8, val: ArtNode<K, V>) -> bool {
-        return false;
+    fn prev_child(&self, before: usize) -> Option<(u8, RawChild<'_, K, V>)> {
+        for byte in (0..before).rev() {
+            let index = self.keys[byte].load(Ordering::Acquire);
+            if index != 0 && index as usize <= self.children.len() {
+                return Some((byte as u8, self.slots().load(index as usize - 1)));
+            }
+        }
+        None
     }
 
-    fn has_child(&self, byte: u8) -> bool {
-        match self.children[byte as usize] {
-            ArtNode::Empty => false,
-            _ => true,
-        }
+    fn change(&self, key: u8, val: ArtNode<K, V>) -> ArtNode<K, V> {
+        let index = self.keys[key as usize].load(Ordering::Relaxed);
+        debug_assert!(index != 0, "changing a missing child");
+        self.slots().swap(index as usize - 1, val)
     }
 }
 
@@ -114,67 +126,41 @@ where
     K: Default + PartialEq + Digital,
     V: 'static + Send + Sync,
 {
-    fn grow(&mut self) -> Node256<K, V> {
-        let mut children = unsafe { make_array!(256, ArtNode::Empty) };
-        for index in 0..255 {
-            let cindex = self
-                .keys
-                .get(index as usize)
-                .unwrap()
-                .load(Ordering::Relaxed);
-            if cindex != 0 {
-                let mut _c = children.get_mut(index as usize).unwrap();
-                _c = self.children.get_mut(cindex as usize).unwrap();
-            }
-        }
-        return Node256 {
-            header: self.header.clone(),
-            children: children,
-            marker: Default::default(),
-        };
+    pub fn slots(&self) -> Slots<'_, K, V> {
+        Slots::new(&self.children, &self.small)
     }
 
-    fn shrink(&mut self) -> Node16<K, V> {
-        //let mut keys: mem::ManuallyDrop<[u8; 16]> = unsafe { mem::uninitialized() };
-        let mut keys: Vec<u8> = Vec::with_capacity(16);
-        let mut children: mem::ManuallyDrop<[ArtNode<K, V>; 16]> = unsafe { mem::uninitialized() };
-        let mut new_children_index = 0;
-        let mut k: mem::ManuallyDrop<[u8; 16]> = unsafe { mem::uninitialized() };
-        for index in 0..255 {
-            let cindex = self
-                .keys
-                .get(index as usize)
-                .unwrap()
-                .load(Ordering::Relaxed);
-            if cindex != 0 {
-                keys.push(index as u8);
-
-                let mut _c = children.get_mut(new_children_index as usize).unwrap();
-                _c = self.children.get_mut(cindex as usize).unwrap();
-                new_children_index += 1;
+    pub fn grow(&self) -> Node256<K, V> {
+        let n = Node256::new();
+        n.header
+            .set_prefix(&self.header.get_partial(), self.header.get_partial_len());
+        for byte in 0..256 {
+            let index = self.keys[byte].load(Ordering::Relaxed);
+            if index != 0 {
+                n.slots().store(byte, self.slots().take(index as usize - 1));
             }
         }
-        unsafe {
-            ptr::copy_nonoverlapping(keys.as_mut_ptr(), k.as_mut_ptr(), 16);
-        }
-
-        return Node16 {
-            header: self.header.clone(),
-            keys: k,
-            children: children,
-            marker: Default::default(),
-        };
+        n.header
+            .num_children
+            .store(self.header.num_children() as u16, Ordering::Relaxed);
+        n
     }
-}
 
-impl<K, V> Drop for Node48<K, V>
-where
-    K: Default + PartialEq + Digital,
-    V: 'static + Send + Sync,
-{
-    fn drop(&mut self) {
-        for i in 0..256 {
-            drop(&mut self.children[i as usize]);
+    pub fn shrink(&self) -> Node16<K, V> {
+        let n = Node16::new();
+        n.header
+            .set_prefix(&self.header.get_partial(), self.header.get_partial_len());
+        let mut count = 0;
+        for byte in 0..256 {
+            let index = self.keys[byte].load(Ordering::Relaxed);
+            if index != 0 {
+                n.keys[count].store(byte as u8, Ordering::Relaxed);
+                n.slots()
+                    .store(count, self.slots().take(index as usize - 1));
+                count += 1;
+            }
         }
+        n.header.num_children.store(count as u16, Ordering::Relaxed);
+        n
     }
 }
@@ -1,114 +1,1955 @@
-use internal::Digital;
-use node::ArtNode::Empty;
+use arrayvec::ArrayVec;
+use cursor::Cursor;
+use epoch::{Collector, Guard, Handle};
+use error::{retry, RadixError, Restart};
+use internal::{encode_to_buf, Digital};
+use iter::{Iter, Range};
 use node::ArtNodeTrait;
 use node::NodeHeader;
-use node::{ArtKey, ArtNode};
+use node::{fits_inline, ArtNode, ArtNodeRef, Leaf, Owned, SmallLeaf, MAX_PREFIX_LEN};
+use node256::Node256;
+use node4::Node4;
+use std::cmp;
+use std::iter;
 use std::marker::PhantomData;
+use std::ops::RangeBounds;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use value::ValueRef;
 
+/// A concurrent adaptive radix tree that maps keys of type `K` to values of type
+/// `V`.
+///
+/// Nodes and leaves unlinked from the tree are freed by its collector, possibly on
+/// another thread and after the tree itself is gone, as long as a `Guard` or a
+/// `Handle` of the tree lives on. Keys and values must therefore be `'static`,
+/// `Send` and `Sync`, since a key that borrows data would be dropped after the
+/// data it borrows:
+///
+/// ```compile_fail,E0597
+/// use radix_tree::{Digital, KeyBuf, Radix};
+///
+/// #[derive(Default, Clone, PartialEq)]
+/// struct Name<'a>(Option<&'a String>);
+///
+/// impl<'a> Digital for Name<'a> {
+///     type Encoded<'b> = KeyBuf where Self: 'b;
+///     const DECODABLE: bool = false;
+///
+///     fn encode(&self) -> KeyBuf {
+///         self.0.map(String::as_str).unwrap_or("").encode()
+///     }
+///
+///     fn decode(_: &[u8]) -> Option<(Self, &[u8])> {
+///         None
+///     }
+/// }
+///
+/// let name = "borrowed".to_string();
+/// let tree: Radix<Name, u32> = Radix::default();
+/// let handle = tree.handle();
+/// tree.insert(Name(Some(&name)), 1, &handle.pin());
+/// tree.insert(Name(Some(&name)), 2, &handle.pin());
+/// drop(tree);
+/// drop(name);
+/// // Drops the replaced leaf, and with it a reference to `name`.
+/// handle.pin().flush();
+/// ```
 pub struct Radix<K, V>
 where
-    K: Default + PartialEq + Digital + ArtKey,
+    K: 'static + Send + Sync + Default + PartialEq + Digital,
     V: 'static + Send + Sync,
 {
-    head: ArtNode<K, V>,
+    // The root is never replaced, so every other node always has a parent to lock.
+    head: Box<Node256<K, V>>,
     size: AtomicUsize,
+    // Nodes and leaves unlinked from the tree are handed to the collector, which
+    // frees them once no guard pinned before the unlink is left.
+    collector: Collector,
     phantom: PhantomData<K>,
 }
 
-impl<K: ArtKey, T> Default for Radix<K, T>
+impl<K, T> Default for Radix<K, T>
 where
-    K: Default + PartialEq + Digital + ArtKey,
+    K: 'static + Send + Sync + Default + PartialEq + Digital,
     T: 'static + Send + Sync,
 {
     fn default() -> Self {
         Radix {
-            head: ArtNode::Empty,
+            head: Box::new(Node256::new()),
             size: AtomicUsize::new(0),
+            collector: Collector::new(),
             phantom: Default::default(),
         }
     }
 }
 
-impl<K: ArtKey, T> Radix<K, T>
+impl<K, T> Radix<K, T>
 where
-    K: Default + Copy + PartialEq + Digital + ArtKey,
+    K: 'static + Send + Sync + Default + Clone + PartialEq + Digital,
     T: 'static + Send + Sync,
 {
-    fn new(level: usize) -> Self {
-        Radix {
-            head: ArtNode::Empty,
-            size: AtomicUsize::new(0),
-            phantom: Default::default(),
+    /// Returns the number of keys stored in the tree.
+    pub fn len(&self) -> usize {
+        self.size.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pins the current thread to the tree's collector.
+    ///
+    /// References handed out by the tree stay valid for as long as the returned
+    /// guard lives. Every call registers a new participant with the collector, so
+    /// a thread that pins often should keep a `handle` and pin through it instead.
+    pub fn pin(&self) -> Guard {
+        self.collector.register().pin()
+    }
+
+    /// Registers the current thread with the tree's collector.
+    ///
+    /// Pinning the handle reuses its registration, which is much cheaper than
+    /// `pin`. The handle cannot leave the thread that created it.
+    pub fn handle(&self) -> Handle {
+        self.collector.register()
+    }
+
+    /// Returns the value stored under `key`.
+    ///
+    /// Lookups never block: every node on the path is read optimistically and the
+    /// whole descent is retried when a concurrent writer invalidates one of them.
+    pub fn get<'g>(&'g self, key: &K, guard: &'g Guard) -> Option<ValueRef<'g, T>> {
+        self.check_guard(guard);
+        let bytes = key.encode();
+        retry(|| self.get_rec(bytes.as_ref()))
+    }
+
+    /// Returns `true` if the tree holds a value for `key`.
+    pub fn contains_key(&self, key: &K, guard: &Guard) -> bool {
+        self.get(key, guard).is_some()
+    }
+
+    fn get_rec(&self, bytes: &[u8]) -> Result<Option<ValueRef<'_, T>>, Restart> {
+        let mut parent: Option<(ArtNodeRef<K, T>, u64)> = None;
+        let mut node = ArtNodeRef::Inner256(&*self.head);
+        let mut depth = 0;
+        loop {
+            if node.is_leaf() {
+                // Keys are compared by their encoding, which tells apart the values
+                // `PartialEq` does not, like the zeros and NaNs of floats.
+                if node.leaf_mismatch(&bytes[..depth], bytes).is_equal() {
+                    return Ok(Some(node.leaf_value()));
+                }
+                return Ok(None);
+            }
+            let header = match node {
+                ArtNodeRef::Empty => return Ok(None),
+                _ => node.header().unwrap(),
+            };
+            let version = match parent {
+                Some((parent, parent_version)) => parent.read_child(parent_version, node)?,
+                None => header.lock.read_lock()?,
+            };
+            depth = match node.prefix_matches(bytes, depth) {
+                Ok(dep) => dep,
+                Err(_) => {
+                    header.lock.check(version)?;
+                    return Ok(None);
+                }
+            };
+            // The child is only trusted once the node proves it was not modified
+            // while we were reading it.
+            let next = match bytes.get(depth) {
+                Some(byte) => node.find_child(*byte, version)?,
+                None => {
+                    header.lock.check(version)?;
+                    None
+                }
+            };
+            match next {
+                Some(child) => {
+                    parent = Some((node, version));
+                    node = child;
+                }
+                None => return Ok(None),
+            }
+            depth += 1;
+        }
+    }
+
+    /// Returns an iterator over the entries of the tree in ascending key byte order.
+    pub fn iter<'g>(&'g self, guard: &'g Guard) -> Iter<'g, K, T> {
+        self.check_guard(guard);
+        Iter::new(ArtNodeRef::Inner256(&*self.head), guard)
+    }
+
+    /// Returns an unpositioned cursor over the entries of the tree.
+    pub fn cursor<'g>(&'g self, guard: &'g Guard) -> Cursor<'g, K, T> {
+        self.check_guard(guard);
+        Cursor::new(ArtNodeRef::Inner256(&*self.head), guard)
+    }
+
+    /// Returns an iterator over the entries whose keys lie within `range`, in
+    /// ascending key byte order.
+    ///
+    /// The iterator descends straight to the lower bound and stops at the first key
+    /// past the upper bound.
+    pub fn range<'g, R: RangeBounds<K>>(&'g self, range: R, guard: &'g Guard) -> Range<'g, K, T> {
+        self.check_guard(guard);
+        Range::new(ArtNodeRef::Inner256(&*self.head), guard, range)
+    }
+
+    /// Returns an iterator over the entries whose key bytes start with `prefix`, in
+    /// ascending key byte order.
+    ///
+    /// The iterator descends to the subtree covering `prefix`, even if `prefix`
+    /// ends within a compressed path, and stops once it leaves that subtree.
+    pub fn scan_prefix<'g>(&'g self, prefix: &[u8], guard: &'g Guard) -> Range<'g, K, T> {
+        self.check_guard(guard);
+        Range::prefix(ArtNodeRef::Inner256(&*self.head), guard, prefix)
+    }
+
+    /// Returns `true` if the key bytes of some entry start with `prefix`.
+    pub fn has_prefix(&self, prefix: &[u8], guard: &Guard) -> bool {
+        self.check_guard(guard);
+        retry(|| self.has_prefix_rec(prefix))
+    }
+
+    fn has_prefix_rec(&self, prefix: &[u8]) -> Result<bool, Restart> {
+        let mut parent: Option<(ArtNodeRef<K, T>, u64)> = None;
+        let mut node = ArtNodeRef::Inner256(&*self.head);
+        let mut depth = 0;
+        loop {
+            let header = node.header().unwrap();
+            let version = match parent {
+                Some((parent, parent_version)) => parent.read_child(parent_version, node)?,
+                None => header.lock.read_lock()?,
+            };
+            let mismatch = node.prefix_mismatch(prefix, depth)?;
+            if mismatch.theirs.is_none() {
+                // `prefix` ends on this node's path, so everything below matches.
+                // Only the root may be without children.
+                let found = header.num_children() > 0;
+                header.lock.check(version)?;
+                return Ok(found);
+            }
+            if mismatch.ours.is_some() {
+                header.lock.check(version)?;
+                return Ok(false);
+            }
+            depth += mismatch.len;
+            let next = node.find_child(prefix[depth], version)?;
+            match next {
+                None => return Ok(false),
+                Some(leaf) if leaf.is_leaf() => {
+                    let path = &prefix[..cmp::min(depth + 1, prefix.len())];
+                    return Ok(leaf.leaf_mismatch(path, prefix).theirs.is_none());
+                }
+                Some(child) => {
+                    parent = Some((node, version));
+                    node = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Inserts `value` under `key`.
+    ///
+    /// Returns the value previously stored under `key`, if there was one.
+    pub fn insert<'g>(&'g self, key: K, value: T, guard: &'g Guard) -> Option<ValueRef<'g, T>> {
+        self.check_guard(guard);
+        // The leaf takes `key`, so its encoding is copied out first.
+        let bytes = encode_to_buf(&key);
+        let mut leaf = Some((key, value));
+        let previous = retry(|| self.insert_rec(bytes.as_ref(), &mut leaf, true, guard));
+        if previous.is_none() {
+            self.size.fetch_add(1, Ordering::SeqCst);
+        }
+        previous
+    }
+
+    /// Inserts `value` under `key` unless the tree already holds a value for `key`.
+    ///
+    /// The value already stored is left alone, and `value` is handed back in a
+    /// `RadixError::KeyExists`.
+    pub fn try_insert(&self, key: K, value: T, guard: &Guard) -> Result<(), RadixError<T>> {
+        self.check_guard(guard);
+        let bytes = encode_to_buf(&key);
+        let mut leaf = Some((key, value));
+        retry(|| self.insert_rec(bytes.as_ref(), &mut leaf, false, guard));
+        match leaf {
+            Some((_, rejected)) => Err(RadixError::KeyExists(rejected)),
+            None => {
+                self.size.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
         }
     }
 
-    fn insert_rec(
-        parent: &ArtNode<K, T>,
-        root: &mut ArtNode<K, T>,
+    /// One optimistic attempt at inserting `leaf`.
+    ///
+    /// Nodes are read without locks while descending. Only the node that gets a new
+    /// child is upgraded to a write lock, together with its parent when the node has
+    /// to be replaced. `leaf` is consumed only once every lock is held, so a failed
+    /// attempt can simply be retried. Without `replace`, a leaf already holding the
+    /// key is kept and `leaf` is left in place.
+    ///
+    /// Leaves are placed right below the first node where their key has no child,
+    /// and only expanded once a second key shares their path.
+    fn insert_rec<'g>(
+        &'g self,
+        bytes: &[u8],
+        leaf: &mut Option<(K, T)>,
+        replace: bool,
+        guard: &'g Guard,
+    ) -> Result<Option<ValueRef<'g, T>>, Restart> {
+        let mut parent: Option<(ArtNodeRef<K, T>, u64, u8)> = None;
+        let mut node = ArtNodeRef::Inner256(&*self.head);
+        let mut depth = 0;
+        // Whether every prefix above `node` is stored in full.
+        let mut complete = true;
+        loop {
+            let header = node.header().unwrap();
+            let version = match parent {
+                Some((parent, parent_version, _)) => parent.read_child(parent_version, node)?,
+                None => header.lock.read_lock()?,
+            };
+
+            // The whole prefix is compared, even the bytes the node does not store,
+            // since the new leaf must not end up below a path that differs from
+            // its key.
+            let mismatch = node.prefix_mismatch(bytes, depth)?;
+            if let Some(prefix_byte) = mismatch.ours {
+                // The key leaves the compressed path inside the prefix: put a new
+                // Node4 holding the common part above `node`.
+                let matched = mismatch.len;
+                let byte = key_byte(bytes, depth + matched);
+                let (tail, tail_len) = node.prefix_tail(depth, matched + 1)?;
+                let (parent, parent_version, parent_key) = parent.unwrap();
+                let parent_header = parent.header().unwrap();
+                parent_header.lock.upgrade(parent_version)?;
+                header
+                    .lock
+                    .upgrade_or_unlock(version, &parent_header.lock)?;
+
+                let split = Node4::new();
+                split.header.set_partial(&bytes[depth..depth + matched]);
+                split.add_child(
+                    self.new_leaf(
+                        bytes,
+                        depth + matched + 1,
+                        complete && matched <= MAX_PREFIX_LEN,
+                        leaf,
+                    ),
+                    byte,
+                );
+                header.set_prefix(&tail, tail_len);
+                split.add_child(parent.change(parent_key, ArtNode::Empty), prefix_byte);
+                parent.change(parent_key, ArtNode::Inner4(Owned::new(split)));
+
+                header.lock.write_unlock();
+                parent_header.lock.write_unlock();
+                return Ok(None);
+            }
+
+            depth += mismatch.len;
+            complete &= header.is_prefix_complete();
+            let byte = key_byte(bytes, depth);
+            let next = node.find_child(byte, version)?;
+
+            match next {
+                None => {
+                    if !node.is_full() {
+                        header.lock.upgrade(version)?;
+                        Self::check_parent(parent, header)?;
+                        node.add_child(self.new_leaf(bytes, depth + 1, complete, leaf), byte);
+                        header.lock.write_unlock();
+                        return Ok(None);
+                    }
+
+                    // The node is full: replace it with a bigger copy, which needs
+                    // the parent locked as well. The root is a Node256 and never
+                    // gets here.
+                    let (parent, parent_version, parent_key) = parent.unwrap();
+                    let parent_header = parent.header().unwrap();
+                    parent_header.lock.upgrade(parent_version)?;
+                    header
+                        .lock
+                        .upgrade_or_unlock(version, &parent_header.lock)?;
+
+                    let bigger = node.grow();
+                    bigger
+                        .as_ref()
+                        .add_child(self.new_leaf(bytes, depth + 1, complete, leaf), byte);
+                    let old = parent.change(parent_key, bigger);
+
+                    header.lock.write_unlock_obsolete();
+                    parent_header.lock.write_unlock();
+                    self.retire(old, guard);
+                    return Ok(None);
+                }
+                Some(ref child) if child.is_leaf() => {
+                    let depth = depth + 1;
+                    let mismatch = child.leaf_mismatch(&bytes[..depth], bytes);
+                    let same = mismatch.is_equal();
+                    if same && !replace {
+                        return Ok(None);
+                    }
+                    if same {
+                        header.lock.upgrade(version)?;
+                        Self::check_parent(parent, header)?;
+                        let old = node.change(byte, self.new_leaf(bytes, depth, complete, leaf));
+                        header.lock.write_unlock();
+                        return Ok(Some(self.retire_value(old, guard)));
+                    }
+
+                    // A leaf hanging above the end of its key: expand it into a Node4
+                    // holding what both keys have in common. Leaves never change, so
+                    // its key can be read before the lock is taken.
+                    let expanded = Node4::new();
+                    expanded.header.set_partial(&bytes[depth..mismatch.len]);
+                    let (old_byte, new_byte) = match (mismatch.ours, mismatch.theirs) {
+                        (Some(old_byte), Some(new_byte)) => (old_byte, new_byte),
+                        _ => panic!("keys must not be prefixes of each other"),
+                    };
+                    header.lock.upgrade(version)?;
+                    Self::check_parent(parent, header)?;
+                    expanded.add_child(node.change(byte, ArtNode::Empty), old_byte);
+                    let prefix_complete = mismatch.len - depth <= MAX_PREFIX_LEN;
+                    expanded.add_child(
+                        self.new_leaf(bytes, mismatch.len + 1, complete && prefix_complete, leaf),
+                        new_byte,
+                    );
+                    node.change(byte, ArtNode::Inner4(Owned::new(expanded)));
+                    header.lock.write_unlock();
+                    return Ok(None);
+                }
+                Some(child) => {
+                    parent = Some((node, version, byte));
+                    node = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Removes `key` from the tree.
+    ///
+    /// Returns the value that was stored under `key`, if there was one.
+    pub fn remove<'g>(&'g self, key: &K, guard: &'g Guard) -> Option<ValueRef<'g, T>> {
+        self.check_guard(guard);
+        let bytes = key.encode();
+        let removed = retry(|| self.remove_rec(bytes.as_ref(), guard));
+        if removed.is_some() {
+            self.size.fetch_sub(1, Ordering::SeqCst);
+        }
+        removed
+    }
+
+    fn remove_rec<'g>(
+        &'g self,
+        bytes: &[u8],
+        guard: &'g Guard,
+    ) -> Result<Option<ValueRef<'g, T>>, Restart> {
+        // Every inner node on the way down, with the version it was read at and
+        // the key byte that was followed.
+        let mut path: Vec<(ArtNodeRef<K, T>, u64, u8)> = Vec::new();
+        let mut node = ArtNodeRef::Inner256(&*self.head);
+        let mut depth = 0;
+        loop {
+            let header = node.header().unwrap();
+            let version = match path.last() {
+                Some(&(parent, parent_version, _)) => parent.read_child(parent_version, node)?,
+                None => header.lock.read_lock()?,
+            };
+            depth = match node.prefix_matches(bytes, depth) {
+                Ok(dep) => dep,
+                Err(_) => {
+                    header.lock.check(version)?;
+                    return Ok(None);
+                }
+            };
+            let next = match bytes.get(depth) {
+                Some(byte) => node.find_child(*byte, version)?.map(|child| (*byte, child)),
+                None => {
+                    header.lock.check(version)?;
+                    None
+                }
+            };
+            match next {
+                None => return Ok(None),
+                Some((byte, ref leaf)) if leaf.is_leaf() => {
+                    if !leaf.leaf_mismatch(&bytes[..depth + 1], bytes).is_equal() {
+                        return Ok(None);
+                    }
+                    path.push((node, version, byte));
+                    self.remove_leaf(&path, guard)?;
+                    return Ok(Some(leaf.leaf_value()));
+                }
+                Some((byte, child)) => {
+                    path.push((node, version, byte));
+                    node = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Unlinks the leaf below the last node of `path`.
+    ///
+    /// Nodes left without children go away together with the leaf. The node that
+    /// loses a child is shrunk once it becomes underfull. A Node4 left with a
+    /// single inner child is merged into that child by prepending its prefix, and
+    /// one left with a single leaf that has its key is replaced by the leaf.
+    fn remove_leaf(
+        &self,
+        path: &[(ArtNodeRef<K, T>, u64, u8)],
+        guard: &Guard,
+    ) -> Result<(), Restart> {
+        let mut top = path.len() - 1;
+        while top > 0 && path[top].0.header().unwrap().num_children() == 1 {
+            top -= 1;
+        }
+        let (node, _, byte) = path[top];
+        let header = node.header().unwrap();
+        // Everything read so far is validated when the versions are upgraded. The
+        // root is never replaced, so it neither shrinks nor collapses.
+        let shrink = top > 0 && node.is_underfull();
+        let collapse = top > 0
+            && match node {
+                ArtNodeRef::Inner4(_) => header.num_children() == 2,
+                _ => false,
+            };
+        let first = if shrink || collapse { top - 1 } else { top };
+        let locked = lock_path(path, first)?;
+
+        self.retire(node.remove_child(byte), guard);
+        for header in &locked[top - first + 1..] {
+            header.lock.write_unlock_obsolete();
+        }
+
+        if shrink {
+            let (parent, _, parent_key) = path[top - 1];
+            let old = parent.change(parent_key, node.shrink());
+            header.lock.write_unlock_obsolete();
+            parent.header().unwrap().lock.write_unlock();
+            self.retire(old, guard);
+            return Ok(());
+        }
+        if collapse {
+            let (parent, _, parent_key) = path[top - 1];
+            let parent_header = parent.header().unwrap();
+            if let ArtNodeRef::Inner4(n4) = node {
+                let (child_key, child) = n4.first_child();
+                if let ArtNodeRef::LeafLarge(_) = child {
+                    // Undo the expansion of a leaf: it takes the place of `node`. A
+                    // small leaf stays where it is, below the last byte of its key.
+                    let old = parent.change(parent_key, n4.remove_child(child_key));
+                    header.lock.write_unlock_obsolete();
+                    parent_header.lock.write_unlock();
+                    self.retire(old, guard);
+                    return Ok(());
+                }
+                if let Some(child_header) = child.header() {
+                    // The child cannot be unlinked while `node` is locked.
+                    let locked = child_header.lock.write_lock();
+                    debug_assert!(locked.is_ok());
+                    let partial_len = header.get_partial_len() + 1 + child_header.get_partial_len();
+                    // Only trees with small leaves need the whole path in their
+                    // prefixes, the others keep just the first bytes.
+                    if partial_len <= MAX_PREFIX_LEN || !self.small_leaves() {
+                        let mut partial: ArrayVec<[u8; MAX_PREFIX_LEN]> = header.get_partial();
+                        // Pushed one by one, `ArrayVec::extend` writes through a
+                        // pointer Miri considers invalid.
+                        for byte in iter::once(child_key).chain(child_header.get_partial()) {
+                            if partial.try_push(byte).is_err() {
+                                break;
+                            }
+                        }
+                        child_header.set_prefix(&partial, partial_len);
+                        let old = parent.change(parent_key, n4.remove_child(child_key));
+                        child_header.lock.write_unlock();
+                        header.lock.write_unlock_obsolete();
+                        parent_header.lock.write_unlock();
+                        self.retire(old, guard);
+                        return Ok(());
+                    }
+                    child_header.lock.write_unlock();
+                }
+            }
+            parent_header.lock.write_unlock();
+        }
+        header.lock.write_unlock();
+        Ok(())
+    }
+
+    /// Fails if `parent` changed since it was read, releasing the write lock of
+    /// `header`, the node below it.
+    fn check_parent(
+        parent: Option<(ArtNodeRef<K, T>, u64, u8)>,
+        header: &NodeHeader,
+    ) -> Result<(), Restart> {
+        match parent {
+            Some((parent, parent_version, _)) => parent
+                .header()
+                .unwrap()
+                .lock
+                .check_or_unlock(parent_version, &header.lock),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes the leaf for the entry pending in `leaf`, to be placed at `depth` of
+    /// its key `bytes`.
+    ///
+    /// The value goes into the child slot when the key can be rebuilt from the path:
+    /// the leaf sits below the last byte of the key and every prefix on the way,
+    /// `complete` tells, is stored in full.
+    fn new_leaf(
+        &self,
+        bytes: &[u8],
         depth: usize,
-        parent_version: usize,
-        key: K,
-        value: T,
-    ) -> Result<(), ()> {
-        let mut next_depth = depth;
-        match root {
-            ArtNode::Empty => print!("1"),
-            ArtNode::Inner4(ptr) => loop {
-                let version = match ptr.header.read_lock_or_restart() {
-                    Err(_) => return Err(()),
-                    Ok(version) => version,
-                };
-                match ptr.prefix_matches(key, depth) {
-                    Ok(dep) => next_depth = dep,
-                    Err(_) => {}
-                };
-                //if !matches!(parent, ArtNode::Empty) {
-                //    if read_unlock_or_restart!(parent, parent_version) {
-                //        return Err(());
-                //    }
-                //};
-            },
-            ArtNode::Inner16(ptr) => loop {
-                let version = match ptr.header.read_lock_or_restart() {
-                    Err(_) => return Err(()),
-                    Ok(version) => version,
-                };
-                //if !matches!(parent, ArtNode::Empty) {
-                //    if read_unlock_or_restart!(parent, parent_version) {
-                //        return Err(());
-                //    }
-                //};
-            },
-            ArtNode::Inner48(ptr) => loop {
-                let version = match ptr.header.read_lock_or_restart() {
-                    Err(_) => return Err(()),
-                    Ok(version) => version,
-                };
-                //if !matches!(parent, ArtNode::Empty) {
-                //    if read_unlock_or_restart!(parent, parent_version) {
-                //        return Err(());
-                //    }
-                //};
-            },
-            ArtNode::Inner256(ptr) => loop {
-                let version = match ptr.header.read_lock_or_restart() {
-                    Err(_) => return Err(()),
-                    Ok(version) => version,
-                };
-                //if !matches!(parent, ArtNode::Empty) {
-                //    if read_unlock_or_restart!(parent, parent_version) {
-                //        return Err(());
-                //    }
-                //};
-            },
-            ArtNode::Value(ptr) => print!("1"),
-        }
-        return Err(());
-    }
-
-    fn insert(&mut self, key: K, value: T) {
-        Self::insert_rec(&ArtNode::Empty, &mut self.head, 0, 0, key, value);
-        self.size.fetch_add(1, Ordering::SeqCst);
+        complete: bool,
+        leaf: &mut Option<(K, T)>,
+    ) -> ArtNode<K, T> {
+        let (key, value) = leaf.take().unwrap();
+        if self.small_leaves() && complete && depth == bytes.len() {
+            ArtNode::LeafSmall(SmallLeaf::new(value))
+        } else {
+            ArtNode::LeafLarge(Owned::new(Leaf(key, value)))
+        }
+    }
+
+    /// Whether leaves of this tree may keep their value in the child slot and leave
+    /// their key to the path, see `fits_inline`.
+    fn small_leaves(&self) -> bool {
+        K::DECODABLE && fits_inline::<T>()
+    }
+
+    /// Panics if `guard` was not pinned by this tree.
+    fn check_guard(&self, guard: &Guard) {
+        if let Some(collector) = guard.collector() {
+            assert!(
+                collector == &self.collector,
+                "guard belongs to a different tree"
+            );
+        }
+    }
+
+    /// Frees `node` once every thread that might still be reading it has unpinned.
+    fn retire(&self, node: ArtNode<K, T>, guard: &Guard) {
+        unsafe { guard.defer(move || drop(node)) }
+    }
+
+    fn retire_value<'g>(&self, node: ArtNode<K, T>, guard: &'g Guard) -> ValueRef<'g, T> {
+        match node {
+            ArtNode::LeafLarge(leaf) => {
+                let value: *const T = &leaf.1;
+                self.retire(ArtNode::LeafLarge(leaf), guard);
+                // The leaf is not freed before `guard` is dropped.
+                ValueRef::borrowed(unsafe { &*value })
+            }
+            ArtNode::LeafSmall(leaf) => ValueRef::copied(leaf.get()),
+            _ => unreachable!("only leaves hold values"),
+        }
+    }
+}
+
+/// Upgrades the nodes of `path` from `first` on to write locks, top down, and
+/// checks that the node above them did not change.
+///
+/// Either all of them end up locked or none of them is.
+fn lock_path<'a, K, T>(
+    path: &[(ArtNodeRef<'a, K, T>, u64, u8)],
+    first: usize,
+) -> Result<Vec<&'a NodeHeader>, Restart>
+where
+    K: Default + PartialEq + Digital,
+    T: 'static + Send + Sync,
+{
+    let mut locked: Vec<&NodeHeader> = Vec::with_capacity(path.len() - first);
+    for (node, version, _) in &path[first..] {
+        let header = node.header().unwrap();
+        if header.lock.upgrade(*version).is_err() {
+            break;
+        }
+        locked.push(header);
+    }
+    let parent_changed = match first.checked_sub(1) {
+        Some(above) => {
+            let (parent, parent_version, _) = path[above];
+            parent.header().unwrap().lock.check(parent_version).is_err()
+        }
+        None => false,
+    };
+    if locked.len() < path.len() - first || parent_changed {
+        for header in locked {
+            header.lock.write_unlock();
+        }
+        return Err(Restart);
+    }
+    Ok(locked)
+}
+
+/// Returns the byte of `bytes` at `depth`.
+///
+/// Runs before any lock is taken so that an unsupported key cannot leave a node
+/// locked behind it.
+fn key_byte(bytes: &[u8], depth: usize) -> u8 {
+    match bytes.get(depth) {
+        Some(byte) => *byte,
+        None => panic!("keys must not be prefixes of each other"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Radix;
+    use epoch::Guard;
+    use error::RadixError;
+    use internal::Digital;
+    use node::{fits_inline, ArtNodeRef, SmallLeaf};
+    use std::fmt::Debug;
+    use std::ops::{Bound, RangeBounds};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[derive(Default, Clone, Copy, PartialEq, Debug)]
+    struct Key([u8; 4]);
+
+    impl Digital for Key {
+        type Encoded<'a> = &'a [u8];
+
+        fn encode(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn decode(bytes: &[u8]) -> Option<(Self, &[u8])> {
+            let (head, rest) = <[u8; 4]>::decode(bytes)?;
+            Some((Key(head), rest))
+        }
+    }
+
+    fn key(n: u32) -> Key {
+        Key([(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8])
+    }
+
+    /// A numbered value that counts its drops, in a counter of its own for every
+    /// test.
+    struct Counted(u32, &'static AtomicUsize);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Follows the children under `bytes` down from the root. The bytes skipped by
+    /// the prefixes on the way are not part of `bytes`.
+    fn descend<'a, K, V>(tree: &'a Radix<K, V>, bytes: &[u8]) -> Option<ArtNodeRef<'a, K, V>>
+    where
+        K: 'static + Send + Sync + Default + Clone + PartialEq + Digital,
+        V: 'static + Send + Sync,
+    {
+        let mut node = ArtNodeRef::Inner256(&*tree.head);
+        for byte in bytes {
+            let version = node.header()?.lock.read_lock().unwrap();
+            node = node.find_child(*byte, version).unwrap()?;
+        }
+        Some(node)
+    }
+
+    /// Returns a tree holding `keys`, each with its position as the value.
+    fn tree_with(keys: &[[u8; 4]]) -> Radix<Key, u32> {
+        let tree = Radix::default();
+        let guard = &tree.pin();
+        for (n, k) in keys.iter().enumerate() {
+            tree.insert(Key(*k), n as u32, guard);
+        }
+        tree
+    }
+
+    #[test]
+    fn get_on_empty_tree() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        assert_eq!(tree.get(&Key([1, 2, 3, 4]), guard), None);
+        assert!(!tree.contains_key(&Key::default(), guard));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn insert_and_get() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        // Spread the keys so that every node type shows up on some level.
+        for n in 0..20_000 {
+            assert_eq!(tree.insert(key(n * 7919), n, guard), None);
+        }
+        assert_eq!(tree.len(), 20_000);
+        for n in 0..20_000 {
+            assert_eq!(tree.get(&key(n * 7919), guard).as_deref(), Some(&n));
+        }
+        assert_eq!(tree.get(&key(1), guard), None);
+    }
+
+    #[test]
+    fn insert_splits_prefix() {
+        let tree: Radix<Key, &str> = Radix::default();
+        let guard = &tree.pin();
+        tree.insert(Key([1, 2, 3, 4]), "a", guard);
+        tree.insert(Key([1, 2, 9, 4]), "b", guard);
+        tree.insert(Key([1, 7, 3, 4]), "c", guard);
+        tree.insert(Key([1, 2, 3, 5]), "d", guard);
+        assert_eq!(tree.get(&Key([1, 2, 3, 4]), guard).as_deref(), Some(&"a"));
+        assert_eq!(tree.get(&Key([1, 2, 9, 4]), guard).as_deref(), Some(&"b"));
+        assert_eq!(tree.get(&Key([1, 7, 3, 4]), guard).as_deref(), Some(&"c"));
+        assert_eq!(tree.get(&Key([1, 2, 3, 5]), guard).as_deref(), Some(&"d"));
+        assert_eq!(tree.get(&Key([1, 2, 3, 6]), guard), None);
+        assert_eq!(tree.get(&Key([1, 2, 4, 4]), guard), None);
+    }
+
+    #[test]
+    fn insert_returns_previous_value() {
+        let tree: Radix<Key, String> = Radix::default();
+        let guard = &tree.pin();
+        assert_eq!(tree.insert(key(42), "first".to_string(), guard), None);
+        assert_eq!(
+            tree.insert(key(42), "second".to_string(), guard).as_deref(),
+            Some(&"first".to_string())
+        );
+        assert_eq!(
+            tree.get(&key(42), guard).as_deref(),
+            Some(&"second".to_string())
+        );
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn handles_pin_repeatedly_and_outlive_the_tree() {
+        let tree: Radix<Key, String> = Radix::default();
+        let handle = tree.handle();
+        for n in 0..200 {
+            let guard = &handle.pin();
+            tree.insert(key(n), n.to_string(), guard);
+            if n % 2 == 1 {
+                assert_eq!(
+                    tree.remove(&key(n - 1), guard).as_deref(),
+                    Some(&(n - 1).to_string())
+                );
+            }
+        }
+        assert_eq!(tree.len(), 100);
+        let guard = &handle.pin();
+        assert_eq!(tree.get(&key(7), guard).as_deref(), Some(&"7".to_string()));
+        assert!(tree.get(&key(8), guard).is_none());
+
+        // The removed leaves wait in the collector, which the handle keeps alive.
+        drop(tree);
+        guard.flush();
+    }
+
+    #[test]
+    fn leaves_retired_before_the_tree_drops_are_freed_through_the_handle() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        let tree: Radix<Key, Counted> = Radix::default();
+        let handle = tree.handle();
+        tree.insert(key(1), Counted(1, &DROPS), &handle.pin());
+        tree.insert(key(1), Counted(2, &DROPS), &handle.pin());
+        tree.insert(key(2), Counted(3, &DROPS), &handle.pin());
+        drop(tree);
+        handle.pin().flush();
+        drop(handle);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn try_insert_hands_back_the_rejected_value() {
+        let tree: Radix<Key, String> = Radix::default();
+        let guard = &tree.pin();
+        assert_eq!(tree.try_insert(key(42), "first".to_string(), guard), Ok(()));
+        assert_eq!(
+            tree.try_insert(key(42), "second".to_string(), guard),
+            Err(RadixError::KeyExists("second".to_string()))
+        );
+        assert_eq!(
+            tree.get(&key(42), guard).as_deref(),
+            Some(&"first".to_string())
+        );
+        assert_eq!(tree.len(), 1);
+
+        let small: Radix<Key, u32> = Radix::default();
+        let guard = &small.pin();
+        assert_eq!(small.try_insert(key(7), 1, guard), Ok(()));
+        assert_eq!(small.try_insert(key(8), 2, guard), Ok(()));
+        assert_eq!(
+            small.try_insert(key(7), 3, guard),
+            Err(RadixError::KeyExists(3))
+        );
+        assert_eq!(small.get(&key(7), guard).as_deref(), Some(&1));
+        assert_eq!(small.len(), 2);
+    }
+
+    #[test]
+    fn retired_leaves_are_reclaimed() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        let tree: Radix<Key, Counted> = Radix::default();
+        for n in 0..1_000 {
+            let guard = &tree.pin();
+            tree.insert(key(n % 10), Counted(n, &DROPPED), guard);
+        }
+        // Replaced leaves are freed while the tree is still in use, the rest goes
+        // away together with it.
+        assert!(DROPPED.load(Ordering::SeqCst) > 0);
+        drop(tree);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1_000);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn remove() {
+        // Small values end up in the child slots, strings in boxed leaves.
+        fn check<T: 'static + Send + Sync + PartialEq + Debug>(value: fn(u32) -> T) {
+            let tree: Radix<Key, T> = Radix::default();
+            let guard = &tree.pin();
+            for n in 0..20_000 {
+                tree.insert(key(n * 7919), value(n), guard);
+            }
+            for n in (0..20_000).filter(|n| n % 3 != 0) {
+                assert_eq!(
+                    tree.remove(&key(n * 7919), guard).as_deref(),
+                    Some(&value(n))
+                );
+            }
+            assert_eq!(tree.remove(&key(7919), guard), None);
+            assert_eq!(tree.len(), 6_667);
+            for n in 0..20_000 {
+                let expected = Some(value(n)).filter(|_| n % 3 == 0);
+                assert_eq!(
+                    tree.get(&key(n * 7919), guard).as_deref(),
+                    expected.as_ref()
+                );
+            }
+            for n in (0..20_000).filter(|n| n % 3 == 0) {
+                assert_eq!(
+                    tree.remove(&key(n * 7919), guard).as_deref(),
+                    Some(&value(n))
+                );
+            }
+            assert!(tree.is_empty());
+            assert_eq!(tree.head.header.num_children(), 0);
+        }
+        check(|n| n);
+        check(|n| n.to_string());
+    }
+
+    #[test]
+    fn remove_merges_single_child_node4() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        tree.insert(Key([1, 2, 3, 4]), 1, guard);
+        tree.insert(Key([1, 2, 3, 5]), 2, guard);
+        tree.insert(Key([1, 9, 9, 9]), 3, guard);
+        assert_eq!(tree.remove(&Key([1, 9, 9, 9]), guard).as_deref(), Some(&3));
+
+        // The Node4 below byte 1 is gone, its remaining child took over its place
+        // and the key byte it hung under.
+        let child = descend(&tree, &[1]).unwrap();
+        assert!(matches!(child, ArtNodeRef::Inner4(_)));
+        assert_eq!(&child.header().unwrap().get_partial()[..], &[2, 3]);
+        assert_eq!(tree.get(&Key([1, 2, 3, 4]), guard).as_deref(), Some(&1));
+        assert_eq!(tree.get(&Key([1, 2, 3, 5]), guard).as_deref(), Some(&2));
+        assert_eq!(tree.get(&Key([1, 9, 9, 9]), guard), None);
+    }
+
+    #[test]
+    fn nodes_hand_over_their_children_when_resized() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        let tree: Radix<Key, Counted> = Radix::default();
+        let lows = || descend(&tree, &[7, 0]).unwrap();
+        {
+            let guard = &tree.pin();
+            for n in 0..300 {
+                tree.insert(
+                    Key([7, 0, (n >> 8) as u8, n as u8]),
+                    Counted(n, &DROPPED),
+                    guard,
+                );
+            }
+            assert!(matches!(lows(), ArtNodeRef::Inner256(_)));
+            for n in 2..256 {
+                let value = tree.remove(&Key([7, 0, 0, n as u8]), guard).unwrap();
+                assert_eq!(value.0, n);
+            }
+            assert!(matches!(lows(), ArtNodeRef::Inner4(_)));
+            for n in 0..300 {
+                let value = tree.get(&Key([7, 0, (n >> 8) as u8, n as u8]), guard);
+                assert_eq!(value.map(|v| v.0), Some(n).filter(|n| *n < 2 || *n >= 256));
+            }
+        }
+        // Every value moved through all four node types and back, and is still
+        // dropped exactly once.
+        drop(tree);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 300);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn concurrent_insert_and_remove() {
+        let tree: Arc<Radix<Key, u32>> = Arc::new(Radix::default());
+        let guard = &tree.pin();
+        for n in 0..20_000 {
+            tree.insert(key(n * 131), n, guard);
+        }
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    let guard = &tree.pin();
+                    for n in 0..5_000 {
+                        let n = n * 4 + t;
+                        if t % 2 == 0 {
+                            assert_eq!(tree.remove(&key(n * 131), guard).as_deref(), Some(&n));
+                        } else {
+                            tree.insert(key(n * 131 + 1), n, guard);
+                            assert_eq!(tree.get(&key(n * 131), guard).as_deref(), Some(&n));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(tree.len(), 20_000);
+        for n in 0..20_000 {
+            let removed = n % 4 == 0 || n % 4 == 2;
+            assert_eq!(tree.get(&key(n * 131), guard).is_none(), removed);
+            assert_eq!(tree.get(&key(n * 131 + 1), guard).is_some(), !removed);
+        }
+    }
+
+    /// Runs `check` over and over while other threads insert and remove 1 << 32,
+    /// which splits and merges the prefix above 0 and 1, and 7, which is added to
+    /// and removed from the node below it.
+    fn alongside_splits_and_merges<F>(check: F)
+    where
+        F: Fn(&Radix<u64, String>, &Guard) + Send + 'static,
+    {
+        let tree: Arc<Radix<u64, String>> = Arc::new(Radix::default());
+        let guard = &tree.pin();
+        tree.insert(0, "0".to_string(), guard);
+        tree.insert(1, "1".to_string(), guard);
+        let done = Arc::new(AtomicBool::new(false));
+        let writers: Vec<_> = [1 << 32, 7]
+            .iter()
+            .map(|&n| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    let guard = &tree.pin();
+                    for _ in 0..100_000 {
+                        assert_eq!(tree.insert(n, n.to_string(), guard), None);
+                        assert_eq!(tree.remove(&n, guard).as_deref(), Some(&n.to_string()));
+                    }
+                })
+            })
+            .collect();
+        let reader = {
+            let (tree, done) = (tree.clone(), done.clone());
+            thread::spawn(move || {
+                let guard = &tree.pin();
+                while !done.load(Ordering::SeqCst) {
+                    check(&tree, guard);
+                }
+            })
+        };
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+        let keys: Vec<u64> = tree.iter(guard).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![0, 1]);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn concurrent_splits_and_merges_keep_keys_in_place() {
+        alongside_splits_and_merges(|tree, guard| {
+            assert!(tree.contains_key(&0, guard));
+            assert!(tree.get(&1, guard).is_some());
+        });
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn has_prefix_alongside_splits_and_merges() {
+        alongside_splits_and_merges(|tree, guard| {
+            assert!(tree.has_prefix(&[0, 0, 0, 0, 0, 0, 0, 1], guard));
+            assert!(!tree.has_prefix(&[0, 0, 0, 0, 1], guard));
+        });
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn cursor_seeks_alongside_splits_and_merges() {
+        alongside_splits_and_merges(|tree, guard| {
+            let mut cursor = tree.cursor(guard);
+            cursor.seek(&(1 << 24));
+            assert!(cursor.key().is_none_or(|k| *k == 1 << 32));
+            cursor.seek_for_prev(&(1 << 24));
+            assert!(cursor.key() == Some(&1) || cursor.key() == Some(&7));
+            cursor.seek(&1);
+            assert_eq!(cursor.key(), Some(&1));
+        });
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn ranges_alongside_splits_and_merges_start_at_their_bound() {
+        alongside_splits_and_merges(|tree, guard| {
+            // Sought at the wrong depth, 1 << 24 would look like 1 and 7 would
+            // follow it.
+            for (k, _) in tree.range(1 << 24.., guard) {
+                assert_eq!(k, 1 << 32);
+            }
+            let keys: Vec<u64> = tree.range(..1 << 24, guard).rev().map(|(k, _)| k).collect();
+            assert!(keys == [1, 0] || keys == [7, 1, 0], "{:?}", keys);
+        });
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn iter_in_key_order() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        assert_eq!(tree.iter(guard).next(), None);
+
+        // Multiplying by an odd constant shuffles the keys, then `key` is
+        // big-endian so byte order and numeric order agree.
+        for n in 0..20_000u32 {
+            let k = n.wrapping_mul(2_654_435_761);
+            tree.insert(key(k), k, guard);
+        }
+        let mut expected: Vec<u32> = (0..20_000u32)
+            .map(|n| n.wrapping_mul(2_654_435_761))
+            .collect();
+        expected.sort();
+        let found: Vec<u32> = tree
+            .iter(guard)
+            .map(|(k, v)| {
+                assert_eq!(k, key(*v));
+                *v
+            })
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn iter_alongside_writers() {
+        let tree: Arc<Radix<Key, u32>> = Arc::new(Radix::default());
+        let guard = &tree.pin();
+        for n in 0..20_000 {
+            tree.insert(key(n * 4), n * 4, guard);
+        }
+        let writer = {
+            let tree = tree.clone();
+            thread::spawn(move || {
+                let guard = &tree.pin();
+                for n in 0..20_000 {
+                    tree.insert(key(n * 4 + 1), n * 4 + 1, guard);
+                    tree.remove(&key(n * 4 + 1), guard);
+                    tree.insert(key(n * 4 + 2), n * 4 + 2, guard);
+                }
+            })
+        };
+        for _ in 0..10 {
+            let mut previous = None;
+            let mut stable = 0;
+            for (_, value) in tree.iter(guard) {
+                assert!(previous < Some(*value));
+                previous = Some(*value);
+                if *value % 4 == 0 {
+                    stable += 1;
+                }
+            }
+            // Keys nobody touches show up exactly once.
+            assert_eq!(stable, 20_000);
+
+            let mut previous = None;
+            let mut stable = 0;
+            for (_, value) in tree.iter(guard).rev() {
+                assert!(previous.is_none_or(|previous| previous > *value));
+                previous = Some(*value);
+                if *value % 4 == 0 {
+                    stable += 1;
+                }
+            }
+            assert_eq!(stable, 20_000);
+        }
+        writer.join().unwrap();
+        assert_eq!(tree.iter(guard).count(), 40_000);
+    }
+
+    fn values<R: RangeBounds<Key>>(tree: &Radix<Key, u32>, range: R) -> Vec<u32> {
+        let guard = &tree.pin();
+        tree.range(range, guard).map(|(_, value)| *value).collect()
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn range_bounds() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        for n in 0..5_000 {
+            tree.insert(key(n * 3), n * 3, guard);
+        }
+        assert_eq!(values(&tree, key(30)..key(39)), vec![30, 33, 36]);
+        assert_eq!(values(&tree, key(30)..=key(39)), vec![30, 33, 36, 39]);
+        assert_eq!(
+            values(&tree, (Bound::Excluded(key(30)), Bound::Included(key(39)))),
+            vec![33, 36, 39]
+        );
+        assert_eq!(values(&tree, ..key(9)), vec![0, 3, 6]);
+        assert_eq!(values(&tree, key(14_990)..), vec![14_991, 14_994, 14_997]);
+        assert_eq!(values(&tree, ..).len(), 5_000);
+        assert_eq!(values(&tree, key(31)..key(33)), vec![]);
+        assert_eq!(values(&tree, key(100_000)..), vec![]);
+    }
+
+    #[test]
+    fn range_seeks_through_prefixes() {
+        let tree = tree_with(&[
+            [1, 2, 3, 4],
+            [1, 2, 3, 5],
+            [1, 2, 9, 4],
+            [1, 7, 3, 4],
+            [5, 5, 5, 5],
+            [5, 5, 5, 6],
+        ]);
+        // Bounds that leave the compressed paths at different bytes.
+        assert_eq!(values(&tree, Key([1, 2, 0, 0])..), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(values(&tree, Key([1, 2, 4, 0])..), vec![2, 3, 4, 5]);
+        assert_eq!(
+            values(&tree, Key([1, 3, 0, 0])..Key([5, 5, 5, 6])),
+            vec![3, 4]
+        );
+        assert_eq!(values(&tree, Key([5, 4, 9, 9])..), vec![4, 5]);
+        assert_eq!(values(&tree, Key([5, 5, 6, 0])..), vec![]);
+        assert_eq!(values(&tree, ..Key([1, 2, 3, 4])), vec![]);
+        assert_eq!(values(&tree, ..=Key([1, 2, 3, 4])), vec![0]);
+    }
+
+    #[test]
+    fn scan_prefix() {
+        let tree = tree_with(&[
+            [1, 2, 3, 4],
+            [1, 2, 3, 5],
+            [1, 2, 9, 4],
+            [1, 7, 3, 4],
+            [1, 255, 255, 1],
+            [2, 0, 0, 0],
+            [255, 255, 0, 1],
+        ]);
+        let guard = &tree.pin();
+        let scan = |prefix: &[u8]| -> Vec<u32> {
+            tree.scan_prefix(prefix, guard)
+                .map(|(_, value)| *value)
+                .collect()
+        };
+        assert_eq!(scan(&[]), vec![0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(scan(&[1]), vec![0, 1, 2, 3, 4]);
+        assert_eq!(scan(&[1, 2]), vec![0, 1, 2]);
+        // Ends within the compressed path [2, 3] below byte 1.
+        assert_eq!(scan(&[1, 2, 3]), vec![0, 1]);
+        assert_eq!(scan(&[1, 2, 3, 5]), vec![1]);
+        assert_eq!(scan(&[1, 255]), vec![4]);
+        assert_eq!(scan(&[255, 255]), vec![6]);
+        assert_eq!(scan(&[1, 2, 4]), vec![]);
+        assert_eq!(scan(&[3]), vec![]);
+
+        assert!(tree.has_prefix(&[], guard));
+        assert!(tree.has_prefix(&[1, 2, 3], guard));
+        assert!(tree.has_prefix(&[1, 2, 9, 4], guard));
+        assert!(tree.has_prefix(&[255], guard));
+        assert!(!tree.has_prefix(&[1, 2, 4], guard));
+        assert!(!tree.has_prefix(&[1, 2, 9, 5], guard));
+        assert!(!tree.has_prefix(&[3], guard));
+
+        let empty: Radix<Key, u32> = Radix::default();
+        assert!(!empty.has_prefix(&[], &empty.pin()));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn iter_rev() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        assert_eq!(tree.iter(guard).next_back(), None);
+        for n in 0..20_000u32 {
+            let k = n.wrapping_mul(2_654_435_761);
+            tree.insert(key(k), k, guard);
+        }
+        let forward: Vec<u32> = tree.iter(guard).map(|(_, value)| *value).collect();
+        let mut backward: Vec<u32> = tree.iter(guard).rev().map(|(_, value)| *value).collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+
+        let latest: Vec<u32> = tree
+            .range(..key(1_000_000), guard)
+            .rev()
+            .take(3)
+            .map(|(_, value)| *value)
+            .collect();
+        let mut expected: Vec<u32> = forward.into_iter().filter(|k| *k < 1_000_000).collect();
+        expected.reverse();
+        expected.truncate(3);
+        assert_eq!(latest, expected);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn range_rev_bounds() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        for n in 0..5_000 {
+            tree.insert(key(n * 3), n * 3, guard);
+        }
+        let rev = |range: (Bound<Key>, Bound<Key>)| -> Vec<u32> {
+            tree.range(range, guard)
+                .rev()
+                .map(|(_, value)| *value)
+                .collect()
+        };
+        assert_eq!(
+            rev((Bound::Included(key(30)), Bound::Excluded(key(39)))),
+            vec![36, 33, 30]
+        );
+        assert_eq!(
+            rev((Bound::Excluded(key(30)), Bound::Included(key(39)))),
+            vec![39, 36, 33]
+        );
+        assert_eq!(
+            rev((Bound::Unbounded, Bound::Excluded(key(9)))),
+            vec![6, 3, 0]
+        );
+        assert_eq!(
+            rev((Bound::Included(key(14_990)), Bound::Unbounded)),
+            vec![14_997, 14_994, 14_991]
+        );
+        assert_eq!(
+            rev((Bound::Included(key(31)), Bound::Excluded(key(33)))),
+            vec![]
+        );
+
+        let tree = tree_with(&[
+            [1, 2, 3, 4],
+            [1, 2, 3, 5],
+            [1, 2, 9, 4],
+            [1, 7, 3, 4],
+            [5, 5, 5, 5],
+        ]);
+        let guard = &tree.pin();
+        // Upper bounds that leave the compressed paths at different bytes.
+        let rev = |end: Key| -> Vec<u32> {
+            tree.range(..end, guard)
+                .rev()
+                .map(|(_, value)| *value)
+                .collect()
+        };
+        assert_eq!(rev(Key([1, 2, 3, 9])), vec![1, 0]);
+        assert_eq!(rev(Key([1, 2, 4, 0])), vec![1, 0]);
+        assert_eq!(rev(Key([1, 6, 0, 0])), vec![2, 1, 0]);
+        assert_eq!(rev(Key([5, 5, 5, 4])), vec![3, 2, 1, 0]);
+        assert_eq!(rev(Key([9, 0, 0, 0])), vec![4, 3, 2, 1, 0]);
+        assert_eq!(rev(Key([1, 2, 3, 4])), vec![]);
+    }
+
+    #[test]
+    fn iter_from_both_ends() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        for n in 0..7 {
+            tree.insert(key(n), n, guard);
+        }
+        let mut iter = tree.iter(guard);
+        assert_eq!(iter.next().map(|(_, v)| *v), Some(0));
+        assert_eq!(iter.next_back().map(|(_, v)| *v), Some(6));
+        assert_eq!(iter.next_back().map(|(_, v)| *v), Some(5));
+        assert_eq!(iter.next().map(|(_, v)| *v), Some(1));
+        assert_eq!(iter.next().map(|(_, v)| *v), Some(2));
+        assert_eq!(iter.next_back().map(|(_, v)| *v), Some(4));
+        assert_eq!(iter.next().map(|(_, v)| *v), Some(3));
+        assert_eq!(iter.next_back(), None);
+        assert_eq!(iter.next(), None);
+
+        // The ends meet between two keys as well as on one.
+        for len in 0..6 {
+            let mut range = tree.range(key(0)..key(len), guard);
+            let mut seen = Vec::new();
+            while let Some((_, v)) = range.next() {
+                seen.push(*v);
+                match range.next_back() {
+                    Some((_, v)) => seen.push(*v),
+                    None => break,
+                }
+            }
+            seen.sort();
+            assert_eq!(seen, (0..len).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn cursor_seek_and_step() {
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        let mut cursor = tree.cursor(guard);
+        assert!(!cursor.valid());
+        cursor.seek_to_first();
+        assert!(!cursor.valid());
+
+        for n in 0..5_000 {
+            tree.insert(key(n * 3), n * 3, guard);
+        }
+        let mut cursor = tree.cursor(guard);
+        cursor.seek(&key(30));
+        assert_eq!(cursor.key(), Some(&key(30)));
+        assert_eq!(cursor.value(), Some(&30));
+        cursor.seek(&key(31));
+        assert_eq!(cursor.value(), Some(&33));
+        cursor.prev();
+        assert_eq!(cursor.value(), Some(&30));
+        cursor.prev();
+        assert_eq!(cursor.value(), Some(&27));
+        cursor.next();
+        cursor.next();
+        assert_eq!(cursor.value(), Some(&33));
+
+        cursor.seek_for_prev(&key(31));
+        assert_eq!(cursor.value(), Some(&30));
+        cursor.seek_for_prev(&key(33));
+        assert_eq!(cursor.value(), Some(&33));
+        cursor.seek_for_prev(&key(100_000));
+        assert_eq!(cursor.value(), Some(&14_997));
+        cursor.next();
+        assert!(!cursor.valid());
+
+        cursor.seek(&key(14_998));
+        assert!(!cursor.valid());
+        cursor.seek(&key(0));
+        cursor.prev();
+        assert!(!cursor.valid());
+
+        // Walking the whole tree both ways visits every entry in order.
+        let mut cursor = tree.cursor(guard);
+        cursor.seek_to_first();
+        let mut forward = Vec::new();
+        while cursor.valid() {
+            forward.push(*cursor.value().unwrap());
+            cursor.next();
+        }
+        assert_eq!(forward, (0..5_000).map(|n| n * 3).collect::<Vec<_>>());
+        cursor.seek_to_last();
+        let mut backward = Vec::new();
+        while cursor.valid() {
+            backward.push(*cursor.value().unwrap());
+            cursor.prev();
+        }
+        backward.reverse();
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn cursor_seeks_through_prefixes() {
+        let tree = tree_with(&[
+            [1, 2, 3, 4],
+            [1, 2, 3, 5],
+            [1, 2, 9, 4],
+            [1, 7, 3, 4],
+            [5, 5, 5, 5],
+        ]);
+        let guard = &tree.pin();
+        let mut cursor = tree.cursor(guard);
+        let mut seek = |k: [u8; 4]| {
+            cursor.seek(&Key(k));
+            let next = cursor.value().cloned();
+            cursor.seek_for_prev(&Key(k));
+            (next, cursor.value().cloned())
+        };
+        assert_eq!(seek([0, 0, 0, 0]), (Some(0), None));
+        assert_eq!(seek([1, 2, 3, 4]), (Some(0), Some(0)));
+        assert_eq!(seek([1, 2, 4, 0]), (Some(2), Some(1)));
+        assert_eq!(seek([1, 2, 0, 0]), (Some(0), None));
+        assert_eq!(seek([1, 3, 0, 0]), (Some(3), Some(2)));
+        assert_eq!(seek([1, 8, 0, 0]), (Some(4), Some(3)));
+        assert_eq!(seek([5, 5, 5, 6]), (None, Some(4)));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn cursor_alongside_writers() {
+        let tree: Arc<Radix<Key, u32>> = Arc::new(Radix::default());
+        let guard = &tree.pin();
+        for n in 0..20_000 {
+            tree.insert(key(n * 4), n * 4, guard);
+        }
+        let writer = {
+            let tree = tree.clone();
+            thread::spawn(move || {
+                let guard = &tree.pin();
+                for n in 0..20_000 {
+                    tree.insert(key(n * 4 + 1), n * 4 + 1, guard);
+                    tree.remove(&key(n * 4 + 1), guard);
+                }
+            })
+        };
+        let mut cursor = tree.cursor(guard);
+        for _ in 0..5 {
+            cursor.seek_to_first();
+            let mut stable = 0;
+            while let Some(&value) = cursor.value() {
+                if value % 4 == 0 {
+                    stable += 1;
+                }
+                cursor.next();
+                assert!(cursor.value().is_none_or(|&next| next > value));
+            }
+            assert_eq!(stable, 20_000);
+
+            cursor.seek_to_last();
+            let mut stable = 0;
+            while let Some(&value) = cursor.value() {
+                if value % 4 == 0 {
+                    stable += 1;
+                }
+                cursor.prev();
+                assert!(cursor.value().is_none_or(|&prev| prev < value));
+            }
+            assert_eq!(stable, 20_000);
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn integer_keys_iterate_in_numeric_order() {
+        let tree: Radix<i64, i64> = Radix::default();
+        let guard = &tree.pin();
+        let mut keys: Vec<i64> = (-500..500).map(|n| n * 1_000_003).collect();
+        keys.extend_from_slice(&[i64::MIN, i64::MAX, -1, 0, 1]);
+        for n in &keys {
+            tree.insert(*n, *n, guard);
+        }
+        keys.sort();
+        keys.dedup();
+        assert_eq!(tree.iter(guard).map(|(k, _)| k).collect::<Vec<_>>(), keys);
+        assert_eq!(
+            tree.range(-2..=1, guard)
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>(),
+            vec![-1, 0, 1]
+        );
+
+        let tree: Radix<u16, u16> = Radix::default();
+        let guard = &tree.pin();
+        for n in (0..1_000).rev() {
+            tree.insert(n * 61, n, guard);
+        }
+        assert_eq!(
+            tree.iter(guard).map(|(_, v)| *v).collect::<Vec<_>>(),
+            (0..1_000).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn float_keys_scan_in_numeric_order() {
+        use std::f64;
+        let tree: Radix<f64, u32> = Radix::default();
+        let guard = &tree.pin();
+        let keys = [
+            3.5,
+            -0.0,
+            f64::NAN,
+            -2.0,
+            0.0,
+            f64::INFINITY,
+            -1e10,
+            0.25,
+            f64::NEG_INFINITY,
+        ];
+        for (n, key) in keys.iter().enumerate() {
+            assert_eq!(tree.insert(*key, n as u32, guard), None);
+        }
+        assert_eq!(tree.len(), keys.len());
+        assert_eq!(tree.get(&f64::NAN, guard).as_deref(), Some(&2));
+        assert_eq!(tree.get(&-0.0, guard).as_deref(), Some(&1));
+        assert_eq!(tree.get(&0.0, guard).as_deref(), Some(&4));
+
+        let scanned: Vec<f64> = tree.range(-5.0..1.0, guard).map(|(k, _)| k).collect();
+        assert_eq!(scanned, vec![-2.0, -0.0, 0.0, 0.25]);
+        assert!(scanned[1].is_sign_negative() && scanned[2].is_sign_positive());
+        let all: Vec<u32> = tree.iter(guard).map(|(_, v)| *v).collect();
+        assert_eq!(all, vec![8, 6, 3, 1, 4, 7, 0, 5, 2]);
+
+        assert_eq!(tree.remove(&f64::NAN, guard).as_deref(), Some(&2));
+        assert_eq!(tree.get(&f64::NAN, guard), None);
+    }
+
+    #[test]
+    fn tuple_keys_scan_by_leading_components() {
+        let tree: Radix<(u32, String, i64), u32> = Radix::default();
+        let guard = &tree.pin();
+        let rows = [
+            (1, "alice", 10),
+            (1, "bob", -5),
+            (1, "bob", 7),
+            (1, "bobby", 0),
+            (2, "alice", 3),
+            (2, "", -1),
+            (1, "bob", 20),
+        ];
+        for (n, &(tenant, name, ts)) in rows.iter().enumerate() {
+            tree.insert((tenant, name.to_string(), ts), n as u32, guard);
+        }
+
+        let scan = |prefix: &[u8]| -> Vec<u32> {
+            tree.scan_prefix(prefix, guard).map(|(_, v)| *v).collect()
+        };
+        assert_eq!(scan((1u32,).encode().as_ref()), vec![0, 1, 2, 6, 3]);
+        assert_eq!(scan((1u32, "bob").encode().as_ref()), vec![1, 2, 6]);
+        assert_eq!(scan((2u32,).encode().as_ref()), vec![5, 4]);
+
+        let key = |tenant: u32, name: &str, ts: i64| (tenant, name.to_string(), ts);
+        let ranged: Vec<u32> = tree
+            .range(key(1, "bob", 0)..key(2, "alice", 3), guard)
+            .map(|(_, v)| *v)
+            .collect();
+        assert_eq!(ranged, vec![2, 6, 3, 5]);
+    }
+
+    #[test]
+    fn string_keys_that_prefix_each_other() {
+        let tree: Radix<String, u32> = Radix::default();
+        let guard = &tree.pin();
+        let words = ["foo", "foobar", "fo", "", "foo\0", "foobarbaz", "bar", "f"];
+        for (n, word) in words.iter().enumerate() {
+            assert_eq!(tree.insert(word.to_string(), n as u32, guard), None);
+        }
+        for (n, word) in words.iter().enumerate() {
+            assert_eq!(
+                tree.get(&word.to_string(), guard).as_deref(),
+                Some(&(n as u32))
+            );
+        }
+        assert_eq!(tree.get(&"foob".to_string(), guard), None);
+
+        let mut sorted = words.to_vec();
+        sorted.sort();
+        let keys: Vec<String> = tree.iter(guard).map(|(k, _)| k).collect();
+        assert_eq!(keys, sorted);
+
+        assert_eq!(tree.remove(&"foo".to_string(), guard).as_deref(), Some(&0));
+        assert_eq!(tree.get(&"foobar".to_string(), guard).as_deref(), Some(&1));
+        assert_eq!(tree.get(&"fo".to_string(), guard).as_deref(), Some(&2));
+
+        let tree: Radix<&[u8], u32> = Radix::default();
+        let guard = &tree.pin();
+        tree.insert(&[1, 2][..], 0, guard);
+        tree.insert(&[1, 2, 0][..], 1, guard);
+        tree.insert(&[1][..], 2, guard);
+        let values: Vec<u32> = tree.iter(guard).map(|(_, v)| *v).collect();
+        assert_eq!(values, vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn long_prefixes_are_checked_against_leaves() {
+        let tree: Radix<&str, u32> = Radix::default();
+        let guard = &tree.pin();
+        tree.insert("https://example.com/users/1", 0, guard);
+        tree.insert("https://example.com/users/2", 1, guard);
+        // One node holds the common part of both keys after the first byte, but
+        // stores only the first bytes of it.
+        let child = descend(&tree, b"h").unwrap();
+        let header = child.header().unwrap();
+        assert_eq!(header.get_partial_len(), "ttps://example.com/users/".len());
+        assert_eq!(&header.get_partial()[..], b"ttps:/");
+
+        // These keys leave the path past the stored bytes.
+        assert_eq!(tree.get(&"https://example.org/users/1", guard), None);
+        assert!(!tree.has_prefix(b"https://example.org", guard));
+        assert!(tree.has_prefix(b"https://example.com/us", guard));
+        let urls = [
+            "https://example.com/users/1",
+            "https://example.com/users/2",
+            "https://example.org/users/1",
+            "https://example.com/",
+            "http://example.com/",
+        ];
+        for (n, url) in urls.iter().enumerate().skip(2) {
+            assert_eq!(tree.insert(url, n as u32, guard), None);
+        }
+        for (n, url) in urls.iter().enumerate() {
+            assert_eq!(tree.get(url, guard).as_deref(), Some(&(n as u32)));
+        }
+        let child = descend(&tree, b"h").unwrap();
+        assert_eq!(&child.header().unwrap().get_partial()[..], b"ttp");
+
+        let keys: Vec<&str> = tree.iter(guard).map(|(k, _)| k).collect();
+        let mut sorted = urls.to_vec();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        let values: Vec<u32> = tree
+            .scan_prefix(b"https://example.com/users/", guard)
+            .map(|(_, v)| *v)
+            .collect();
+        assert_eq!(values, vec![0, 1]);
+        let values: Vec<u32> = tree
+            .range("https://example.com/v".., guard)
+            .map(|(_, v)| *v)
+            .collect();
+        assert_eq!(values, vec![2]);
+        let mut cursor = tree.cursor(guard);
+        cursor.seek(&"https://example.com/users/15");
+        assert_eq!(cursor.key(), Some(&"https://example.com/users/2"));
+
+        assert_eq!(
+            tree.remove(&"https://example.com/users/2", guard)
+                .as_deref(),
+            Some(&1)
+        );
+        assert_eq!(
+            tree.remove(&"http://example.com/", guard).as_deref(),
+            Some(&4)
+        );
+        assert_eq!(tree.remove(&"https://example.com/users/3", guard), None);
+        for (n, url) in urls.iter().enumerate().take(4) {
+            let expected = if n == 1 { None } else { Some(n as u32) };
+            assert_eq!(tree.get(url, guard).as_deref().cloned(), expected);
+        }
+        assert_eq!(tree.len(), 3);
+    }
+
+    #[test]
+    fn lone_leaves_hang_as_high_as_possible() {
+        let tree: Radix<&str, u32> = Radix::default();
+        let guard = &tree.pin();
+        tree.insert("apple", 0, guard);
+        tree.insert("banana", 1, guard);
+        assert!(descend(&tree, b"a").unwrap().is_leaf());
+
+        // A second key below `a` expands the leaf into a Node4.
+        tree.insert("apricot", 2, guard);
+        let child = descend(&tree, b"a").unwrap();
+        assert_eq!(&child.header().unwrap().get_partial()[..], b"p");
+        assert!(descend(&tree, b"ap").unwrap().is_leaf());
+        assert!(descend(&tree, b"ar").unwrap().is_leaf());
+        assert_eq!(tree.insert("apple", 3, guard).as_deref(), Some(&0));
+        assert_eq!(tree.get(&"app", guard), None);
+        assert_eq!(tree.get(&"applesauce", guard), None);
+        let keys: Vec<&str> = tree.iter(guard).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["apple", "apricot", "banana"]);
+
+        // Removing it again collapses the Node4 back into the leaf left.
+        assert_eq!(tree.remove(&"apricot", guard).as_deref(), Some(&2));
+        assert!(descend(&tree, b"a").unwrap().is_leaf());
+        assert_eq!(tree.get(&"apple", guard).as_deref(), Some(&3));
+        assert_eq!(tree.remove(&"apple", guard).as_deref(), Some(&3));
+        assert_eq!(tree.head.header.num_children(), 1);
+    }
+
+    #[test]
+    fn small_values_stay_in_child_slots() {
+        assert!(fits_inline::<u32>() && fits_inline::<char>() && fits_inline::<()>());
+        assert!(fits_inline::<u64>() && fits_inline::<f64>() && fits_inline::<isize>());
+        assert!(!fits_inline::<u128>() && !fits_inline::<String>());
+        assert!(!fits_inline::<(u8, u8)>());
+        for value in &[i16::MIN, -1, 0, 1, i16::MAX] {
+            assert_eq!(SmallLeaf::new(*value).get(), *value);
+        }
+        for value in &[0, 1, u64::MAX, 1 << 63] {
+            assert_eq!(SmallLeaf::new(*value).get(), *value);
+        }
+        assert_eq!(SmallLeaf::new('\u{10ffff}').get(), '\u{10ffff}');
+
+        // A lone leaf hangs right below the root with its key. Once expanded, the
+        // new leaf sits below the last byte of its key and keeps just its value.
+        let tree: Radix<Key, u32> = Radix::default();
+        let guard = &tree.pin();
+        tree.insert(Key([1, 2, 3, 4]), 7, guard);
+        assert!(matches!(
+            descend(&tree, &[1]),
+            Some(ArtNodeRef::LeafLarge(_))
+        ));
+        tree.insert(Key([1, 2, 3, 5]), 8, guard);
+        let child = descend(&tree, &[1]).unwrap();
+        assert_eq!(&child.header().unwrap().get_partial()[..], &[2, 3]);
+        assert!(matches!(
+            descend(&tree, &[1, 4]),
+            Some(ArtNodeRef::LeafLarge(_))
+        ));
+        assert!(matches!(
+            descend(&tree, &[1, 5]),
+            Some(ArtNodeRef::LeafSmall(_))
+        ));
+        assert_eq!(tree.get(&Key([1, 2, 3, 4]), guard).as_deref(), Some(&7));
+        assert_eq!(tree.get(&Key([1, 2, 3, 5]), guard).as_deref(), Some(&8));
+
+        // Dense keys fill the last level with whole words.
+        let tree: Radix<u64, u64> = Radix::default();
+        let guard = &tree.pin();
+        for n in 0..1_000 {
+            tree.insert(n, u64::MAX - n, guard);
+        }
+        assert!(matches!(
+            descend(&tree, &[0, 3, 0xe7]),
+            Some(ArtNodeRef::LeafSmall(_))
+        ));
+        assert_eq!(tree.get(&999, guard).as_deref(), Some(&(u64::MAX - 999)));
+        let entries: Vec<(u64, u64)> = tree.iter(guard).map(|(k, v)| (k, *v)).collect();
+        assert_eq!(
+            entries,
+            (0..1_000).map(|n| (n, u64::MAX - n)).collect::<Vec<_>>()
+        );
+        for n in (0..1_000).step_by(2) {
+            assert_eq!(tree.remove(&n, guard).as_deref(), Some(&(u64::MAX - n)));
+        }
+        assert_eq!(tree.get(&998, guard), None);
+        assert_eq!(tree.get(&999, guard).as_deref(), Some(&(u64::MAX - 999)));
+        assert_eq!(tree.len(), 500);
+
+        let tree: Radix<(u16, String), char> = Radix::default();
+        let guard = &tree.pin();
+        for (n, name) in ["b", "a", "ab", ""].iter().enumerate() {
+            let value = (b'w' + n as u8) as char;
+            assert_eq!(
+                tree.insert((n as u16 % 2, name.to_string()), value, guard),
+                None
+            );
+        }
+        assert_eq!(
+            tree.get(&(0, "b".to_string()), guard).as_deref(),
+            Some(&'w')
+        );
+        assert_eq!(
+            tree.insert((0, "b".to_string()), 'v', guard).as_deref(),
+            Some(&'w')
+        );
+        let entries: Vec<((u16, String), char)> = tree.iter(guard).map(|(k, v)| (k, *v)).collect();
+        let expected = vec![
+            ((0, "ab".to_string()), 'y'),
+            ((0, "b".to_string()), 'v'),
+            ((1, "".to_string()), 'z'),
+            ((1, "a".to_string()), 'x'),
+        ];
+        assert_eq!(entries, expected);
+        let mut reversed: Vec<_> = tree.iter(guard).rev().map(|(k, v)| (k, *v)).collect();
+        reversed.reverse();
+        assert_eq!(reversed, expected);
+
+        let mut cursor = tree.cursor(guard);
+        cursor.seek(&(0, "c".to_string()));
+        assert_eq!(cursor.key(), Some(&(1, "".to_string())));
+        cursor.prev();
+        assert_eq!(cursor.key(), Some(&(0, "b".to_string())));
+        assert_eq!(cursor.value(), Some(&'v'));
+
+        assert_eq!(
+            tree.remove(&(1, "a".to_string()), guard).as_deref(),
+            Some(&'x')
+        );
+        assert_eq!(tree.len(), 3);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn concurrent_insert() {
+        let tree: Arc<Radix<Key, u32>> = Arc::new(Radix::default());
+        let guard = &tree.pin();
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    let guard = &tree.pin();
+                    for n in 0..5_000 {
+                        let n = n * 4 + t;
+                        tree.insert(key(n * 131), n, guard);
+                        assert_eq!(tree.get(&key(n * 131), guard).as_deref(), Some(&n));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(tree.len(), 20_000);
+        for n in 0..20_000 {
+            assert_eq!(tree.get(&key(n * 131), guard).as_deref(), Some(&n));
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn concurrent_try_insert_admits_one_value_per_key() {
+        let tree: Arc<Radix<Key, u32>> = Arc::new(Radix::default());
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    let guard = &tree.pin();
+                    let mut admitted = 0;
+                    for n in 0..2_000 {
+                        match tree.try_insert(key(n * 131), t, guard) {
+                            Ok(()) => admitted += 1,
+                            Err(RadixError::KeyExists(value)) => assert_eq!(value, t),
+                        }
+                    }
+                    admitted
+                })
+            })
+            .collect();
+        let admitted: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(admitted, 2_000);
+        assert_eq!(tree.len(), 2_000);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn concurrent_insert_and_remove_long_keys() {
+        fn url(n: u64) -> String {
+            format!("https://example.com/users/{}/profile", n)
+        }
+        let tree: Arc<Radix<String, u64>> = Arc::new(Radix::default());
+        let guard = &tree.pin();
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    let guard = &tree.pin();
+                    for n in 0..2_000 {
+                        let n = n * 4 + t;
+                        tree.insert(url(n), n, guard);
+                        assert_eq!(tree.get(&url(n), guard).as_deref(), Some(&n));
+                        if n % 2 == 1 {
+                            assert_eq!(tree.remove(&url(n), guard).as_deref(), Some(&n));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(tree.len(), 4_000);
+        for n in 0..8_000 {
+            let expected = if n % 2 == 0 { Some(&n) } else { None };
+            assert_eq!(tree.get(&url(n), guard).as_deref(), expected);
+        }
     }
 }
@@ -0,0 +1,56 @@
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+
+/// A value read from a `Radix`, valid for as long as the guard it was read with.
+///
+/// Values are usually borrowed from their leaf. Small values of trees whose keys
+/// decode may live in the child slot of their parent node instead, which writers
+/// may overwrite at any time, so they are copied out.
+pub struct ValueRef<'g, T: 'g> {
+    inner: Inner<'g, T>,
+}
+
+enum Inner<'g, T: 'g> {
+    Borrowed(&'g T),
+    // Only types without drop glue are kept in child slots, so the copy never
+    // needs to be dropped.
+    Copied(ManuallyDrop<T>),
+}
+
+impl<'g, T> ValueRef<'g, T> {
+    pub(crate) fn borrowed(value: &'g T) -> Self {
+        ValueRef {
+            inner: Inner::Borrowed(value),
+        }
+    }
+
+    pub(crate) fn copied(value: T) -> Self {
+        ValueRef {
+            inner: Inner::Copied(ManuallyDrop::new(value)),
+        }
+    }
+}
+
+impl<'g, T> Deref for ValueRef<'g, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self.inner {
+            Inner::Borrowed(value) => value,
+            Inner::Copied(ref value) => value,
+        }
+    }
+}
+
+impl<'g, T: PartialEq> PartialEq for ValueRef<'g, T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<'g, T: fmt::Debug> fmt::Debug for ValueRef<'g, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}